@@ -0,0 +1,39 @@
+CREATE TABLE users (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(64) NOT NULL UNIQUE,
+    password VARCHAR(255) NOT NULL
+);
+
+CREATE TABLE races (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(64) NOT NULL UNIQUE
+);
+
+CREATE TABLE classes (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(64) NOT NULL UNIQUE
+);
+
+CREATE TABLE characters (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    account BIGINT NOT NULL,
+    name VARCHAR(64) NOT NULL UNIQUE,
+    race BIGINT NOT NULL,
+    class BIGINT NOT NULL,
+    room BIGINT NOT NULL,
+    FOREIGN KEY (account) REFERENCES users (id) ON DELETE CASCADE,
+    FOREIGN KEY (race) REFERENCES races (id),
+    FOREIGN KEY (class) REFERENCES classes (id),
+    INDEX characters_account (account)
+);
+
+INSERT INTO races (id, name) VALUES
+    (1, 'Human'),
+    (2, 'Elf'),
+    (3, 'Orc'),
+    (4, 'Pixie');
+
+INSERT INTO classes (id, name) VALUES
+    (1, 'Warrior'),
+    (2, 'Mage'),
+    (3, 'Cleric');
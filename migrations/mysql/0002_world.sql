@@ -0,0 +1,32 @@
+CREATE TABLE areas (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(128) NOT NULL
+);
+
+CREATE TABLE rooms (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    area BIGINT NULL,
+    name VARCHAR(128) NOT NULL,
+    description TEXT NOT NULL,
+    FOREIGN KEY (area) REFERENCES areas (id) ON DELETE SET NULL
+);
+
+CREATE TABLE exits (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    from_room BIGINT NOT NULL,
+    to_room BIGINT NOT NULL,
+    direction VARCHAR(64) NOT NULL,
+    FOREIGN KEY (from_room) REFERENCES rooms (id) ON DELETE CASCADE,
+    FOREIGN KEY (to_room) REFERENCES rooms (id) ON DELETE CASCADE,
+    UNIQUE (from_room, direction)
+);
+
+INSERT INTO areas (id, name) VALUES (1, 'Test');
+
+INSERT INTO rooms (id, area, name, description) VALUES
+    (1, 1, 'Test', 'A simple room. Nothing to see here.'),
+    (2, 1, 'Test2', 'Another room.');
+
+INSERT INTO exits (from_room, to_room, direction) VALUES
+    (1, 2, 'north'),
+    (2, 1, 'south');
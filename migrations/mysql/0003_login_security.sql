@@ -0,0 +1,15 @@
+-- Timestamps are seconds since the Unix epoch
+ALTER TABLE users
+    ADD COLUMN failed_logins BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN locked_until BIGINT NULL;
+
+CREATE TABLE login_audit (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    user_id BIGINT NULL,
+    username VARCHAR(64) NOT NULL,
+    peer_addr VARCHAR(64) NOT NULL,
+    success BOOLEAN NOT NULL,
+    created_at BIGINT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE SET NULL,
+    INDEX (created_at)
+);
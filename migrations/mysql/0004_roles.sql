@@ -0,0 +1,2 @@
+-- One of player, builder, admin or owner
+ALTER TABLE users ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'player';
@@ -0,0 +1,9 @@
+-- One-time codes issued by admins. Timestamps are seconds since the Unix epoch.
+CREATE TABLE password_resets (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    code_hash VARCHAR(255) NOT NULL,
+    expires_at BIGINT NOT NULL,
+    used_at BIGINT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
@@ -0,0 +1,13 @@
+-- Names are unique regardless of case, whatever the server's default collation is. Names that
+-- only differ in case from an older one get their id appended first, so the change can't fail.
+UPDATE users JOIN users AS older
+    ON LOWER(older.username) = LOWER(users.username) AND older.id < users.id
+SET users.username = CONCAT(users.username, '_', users.id);
+UPDATE characters JOIN characters AS older
+    ON LOWER(older.name) = LOWER(characters.name) AND older.id < characters.id
+SET characters.name = CONCAT(characters.name, '_', characters.id);
+
+ALTER TABLE users
+    MODIFY username VARCHAR(64) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL;
+ALTER TABLE characters
+    MODIFY name VARCHAR(64) CHARACTER SET utf8mb4 COLLATE utf8mb4_general_ci NOT NULL;
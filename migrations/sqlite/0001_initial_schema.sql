@@ -0,0 +1,37 @@
+CREATE TABLE users (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL
+);
+
+CREATE TABLE races (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE classes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE characters (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    account INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name TEXT NOT NULL UNIQUE,
+    race INTEGER NOT NULL REFERENCES races (id),
+    class INTEGER NOT NULL REFERENCES classes (id),
+    room INTEGER NOT NULL
+);
+
+CREATE INDEX characters_account ON characters (account);
+
+INSERT INTO races (id, name) VALUES
+    (1, 'Human'),
+    (2, 'Elf'),
+    (3, 'Orc'),
+    (4, 'Pixie');
+
+INSERT INTO classes (id, name) VALUES
+    (1, 'Warrior'),
+    (2, 'Mage'),
+    (3, 'Cleric');
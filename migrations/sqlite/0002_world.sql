@@ -0,0 +1,29 @@
+CREATE TABLE areas (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE rooms (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    area INTEGER NULL REFERENCES areas (id) ON DELETE SET NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE exits (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    from_room INTEGER NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    to_room INTEGER NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    direction TEXT NOT NULL,
+    UNIQUE (from_room, direction)
+);
+
+INSERT INTO areas (id, name) VALUES (1, 'Test');
+
+INSERT INTO rooms (id, area, name, description) VALUES
+    (1, 1, 'Test', 'A simple room. Nothing to see here.'),
+    (2, 1, 'Test2', 'Another room.');
+
+INSERT INTO exits (from_room, to_room, direction) VALUES
+    (1, 2, 'north'),
+    (2, 1, 'south');
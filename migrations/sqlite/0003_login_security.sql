@@ -0,0 +1,14 @@
+-- Timestamps are seconds since the Unix epoch
+ALTER TABLE users ADD COLUMN failed_logins INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until INTEGER NULL;
+
+CREATE TABLE login_audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NULL REFERENCES users (id) ON DELETE SET NULL,
+    username TEXT NOT NULL,
+    peer_addr TEXT NOT NULL,
+    success INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX login_audit_created_at ON login_audit (created_at);
@@ -0,0 +1,2 @@
+-- One of player, builder, admin or owner
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'player';
@@ -0,0 +1,8 @@
+-- One-time codes issued by admins. Timestamps are seconds since the Unix epoch.
+CREATE TABLE password_resets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    expires_at INTEGER NOT NULL,
+    used_at INTEGER NULL
+);
@@ -0,0 +1,15 @@
+-- Names are unique regardless of case. Names that only differ in case from an older one get their
+-- id appended first, so creating the indexes can't fail.
+UPDATE users SET username = username || '_' || id
+WHERE EXISTS (
+    SELECT 1 FROM users AS older
+    WHERE LOWER(older.username) = LOWER(users.username) AND older.id < users.id
+);
+UPDATE characters SET name = name || '_' || id
+WHERE EXISTS (
+    SELECT 1 FROM characters AS older
+    WHERE LOWER(older.name) = LOWER(characters.name) AND older.id < characters.id
+);
+
+CREATE UNIQUE INDEX users_username_lower ON users (LOWER(username));
+CREATE UNIQUE INDEX characters_name_lower ON characters (LOWER(name));
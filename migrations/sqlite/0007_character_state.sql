@@ -0,0 +1,3 @@
+-- Seconds spent in the world, and when the character was last saved
+ALTER TABLE characters ADD COLUMN played INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE characters ADD COLUMN last_seen INTEGER NULL;
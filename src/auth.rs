@@ -1,17 +1,31 @@
 use std::{
+    collections::HashMap,
     fmt::Display,
-    sync::{Arc, RwLock},
+    net::IpAddr,
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, Instant},
 };
 
 use bevy::prelude::*;
 use bevy_yarnspinner::{events::ExecuteCommandEvent, prelude::*};
+use sqlx::AnyPool;
 
 use crate::{
     class::Classes,
-    database::DatabaseCommandsEx,
+    config::ServerConfig,
+    database::{DatabaseCommandsEx, name_equals},
     menu::{EnterMenu, MenuLibrary},
+    misc::Id,
+    name_policy::{NamePolicies, set_error, set_rejection},
+    permissions::Role,
+    persistence::PlayTime,
     race::Races,
-    telnet::{Connection, EventWriterTelnetEx, NewConnection, SendMessageAction},
+    session::{Character, ControlledBy, LinkDead, ReconnectAction, SessionSettings},
+    telnet::{
+        Connection, DisconnectAction, EventWriterTelnetEx, NewConnection, Secure, SendMessageAction,
+    },
+    util::unix_time,
+    world::room::Room,
 };
 
 pub struct AuthPlugin;
@@ -20,9 +34,11 @@ impl Plugin for AuthPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<Username>()
             .register_type::<LoggedIn>()
+            .init_resource::<LoginSettings>()
+            .init_resource::<LoginThrottle>()
             .add_event::<CharacterLoginEvent>()
             .add_systems(Startup, register_library_functions)
-            .add_systems(Update, start_login)
+            .add_systems(Update, (start_login, finish_login_delays))
             .add_systems(
                 Update,
                 (
@@ -30,29 +46,39 @@ impl Plugin for AuthPlugin {
                     on_register_account_command,
                     on_print_char_selection_command,
                     on_choose_char_command,
+                    on_take_over_session_command,
                 )
                     .after(YarnSpinnerSystemSet),
             );
     }
 }
 
+/// Fires when a character enters the world
+/// Event target is the newly spawned character
 #[derive(Clone, Debug, Event)]
 pub struct CharacterLoginEvent {
+    pub id: u64,
     pub name: String,
     pub account: u64,
     pub class: u64,
     pub race: u64,
+    /// Id of the room the character enters, which is known to exist
     pub room: u64,
 }
 
 fn register_library_functions(mut commands: Commands, mut library: ResMut<MenuLibrary>) {
     library.add_function("is_logged_in", commands.register_system(is_logged_in));
+    library.add_function("is_secure", commands.register_system(is_secure));
 }
 
 fn is_logged_in(In((entity, ())): In<(Entity, ())>, query: Query<(), With<LoggedIn>>) -> bool {
     query.contains(entity)
 }
 
+fn is_secure(In((entity, ())): In<(Entity, ())>, query: Query<(), With<Secure>>) -> bool {
+    query.contains(entity)
+}
+
 fn start_login(mut commands: Commands, mut conns: EventReader<NewConnection>) {
     for player in conns.read() {
         commands
@@ -61,10 +87,231 @@ fn start_login(mut commands: Commands, mut conns: EventReader<NewConnection>) {
     }
 }
 
+#[derive(Resource, Clone, Debug)]
+pub struct LoginSettings {
+    /// Failed logins in a row before an account is locked
+    pub max_failed_logins: u32,
+    pub lockout_duration: Duration,
+    /// Delay after the first failed login, doubling with every further failure
+    pub failure_delay: Duration,
+    pub max_failure_delay: Duration,
+    /// Time after which failed logins from an address are forgotten
+    pub address_failure_window: Duration,
+    /// bcrypt cost of new password hashes. Older hashes with a lower cost are replaced on login.
+    pub password_cost: u32,
+    /// How long password reset codes stay valid
+    pub reset_code_lifetime: Duration,
+}
+
+impl Default for LoginSettings {
+    fn default() -> Self {
+        Self {
+            max_failed_logins: 5,
+            lockout_duration: Duration::from_secs(900),
+            failure_delay: Duration::from_millis(500),
+            max_failure_delay: Duration::from_secs(30),
+            address_failure_window: Duration::from_secs(900),
+            password_cost: bcrypt::DEFAULT_COST,
+            reset_code_lifetime: Duration::from_secs(86400),
+        }
+    }
+}
+
+impl LoginSettings {
+    /// How long to wait before reporting the `failures`th failed login in a row
+    fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+
+        self.failure_delay
+            .saturating_mul(2u32.saturating_pow(failures - 1))
+            .min(self.max_failure_delay)
+    }
+}
+
+/// Recent failed logins and password checks by remote address
+#[derive(Resource, Default)]
+pub struct LoginThrottle(HashMap<IpAddr, AddressFailures>);
+
+struct AddressFailures {
+    count: u32,
+    last: Instant,
+}
+
+impl LoginThrottle {
+    /// Records a failed login from `addr` and returns the number of recent failures
+    fn fail(&mut self, addr: IpAddr, window: Duration) -> u32 {
+        let now = Instant::now();
+        let failures = self.0.entry(addr).or_insert(AddressFailures {
+            count: 0,
+            last: now,
+        });
+
+        if now.duration_since(failures.last) > window {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+        failures.count
+    }
+
+    fn forget_expired(&mut self, window: Duration) {
+        self.0
+            .retain(|_, failures| failures.last.elapsed() <= window);
+    }
+}
+
+/// Holds back the result of a failed login until the timer finishes
+#[derive(Component)]
+struct LoginDelay {
+    timer: Timer,
+    finished: Arc<RwLock<bool>>,
+}
+
+/// Counts a failed password check from `addr`, and holds back reporting it to the dialogue on
+/// `conn` until `finished` is set. The delay grows with `failures` or the failures from `addr`,
+/// whichever is higher.
+pub fn delay_failure(
+    commands: &mut Commands,
+    settings: &LoginSettings,
+    throttle: &mut LoginThrottle,
+    conn: Entity,
+    addr: IpAddr,
+    failures: u32,
+    finished: Arc<RwLock<bool>>,
+) {
+    let failures = failures.max(throttle.fail(addr, settings.address_failure_window));
+    commands.entity(conn).insert(LoginDelay {
+        timer: Timer::new(settings.delay(failures), TimerMode::Once),
+        finished,
+    });
+}
+
+enum LoginOutcome {
+    Success(u64, Role),
+    /// Wrong username or password, with the number of failures in a row on the account
+    Failed(u32),
+    Locked,
+    SQLError,
+}
+
+/// Hash checked against when the username doesn't exist, so that it takes as long to reject as a
+/// wrong password and doesn't reveal which accounts exist
+fn dummy_hash(cost: u32) -> Option<&'static str> {
+    static DUMMY_HASH: OnceLock<Option<String>> = OnceLock::new();
+    DUMMY_HASH
+        .get_or_init(|| bcrypt::hash("", cost).ok())
+        .as_deref()
+}
+
+/// Cost a bcrypt hash was created with, from a hash like `$2b$12$...`
+fn hash_cost(hash: &str) -> Option<u32> {
+    hash.split('$').nth(2)?.parse().ok()
+}
+
+async fn audit_login(
+    pool: &AnyPool,
+    account: Option<u64>,
+    username: &str,
+    peer_addr: &str,
+    success: bool,
+) {
+    let result = sqlx::query(
+        "INSERT INTO login_audit (user_id, username, peer_addr, success, created_at) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(account.map(|account| account as i64))
+    .bind(username)
+    .bind(peer_addr)
+    .bind(success)
+    .bind(unix_time())
+    .execute(pool)
+    .await;
+
+    if let Err(err) = result {
+        warn!("Could not record login of {username} from {peer_addr}: {err}");
+    }
+}
+
+async fn check_login(
+    pool: &AnyPool,
+    settings: &LoginSettings,
+    username: &str,
+    password: &str,
+) -> Result<LoginOutcome, sqlx::Error> {
+    let sql = format!(
+        "SELECT id, password, role, failed_logins, locked_until FROM users WHERE {}",
+        name_equals(pool, "username")
+    );
+    let user: Option<(i64, String, String, i64, Option<i64>)> = sqlx::query_as(&sql)
+        .bind(username)
+        .fetch_optional(pool)
+        .await?;
+
+    let Some((acc_id, hash, role, failed_logins, locked_until)) = user else {
+        if let Some(hash) = dummy_hash(settings.password_cost) {
+            let _ = bcrypt::verify(password, hash);
+        }
+        return Ok(LoginOutcome::Failed(0));
+    };
+
+    let now = unix_time();
+    if locked_until.is_some_and(|locked_until| locked_until > now) {
+        return Ok(LoginOutcome::Locked);
+    }
+
+    if let Ok(true) = bcrypt::verify(password, &hash) {
+        sqlx::query("UPDATE users SET failed_logins = 0, locked_until = NULL WHERE id = ?")
+            .bind(acc_id)
+            .execute(pool)
+            .await?;
+
+        if hash_cost(&hash).is_some_and(|cost| cost < settings.password_cost) {
+            match bcrypt::hash(password, settings.password_cost) {
+                Ok(hash) => {
+                    sqlx::query("UPDATE users SET password = ? WHERE id = ?")
+                        .bind(hash)
+                        .bind(acc_id)
+                        .execute(pool)
+                        .await?;
+                    debug!("Rehashed password of {username}");
+                }
+                Err(err) => warn!("Could not rehash password of {username}: {err}"),
+            }
+        }
+
+        let role = role.parse().unwrap_or_else(|err| {
+            warn!("{err} for account {username}");
+            Role::Player
+        });
+        return Ok(LoginOutcome::Success(acc_id as u64, role));
+    }
+
+    let failed_logins = failed_logins as u32 + 1;
+    if failed_logins >= settings.max_failed_logins {
+        let locked_until = now + settings.lockout_duration.as_secs() as i64;
+        sqlx::query("UPDATE users SET failed_logins = 0, locked_until = ? WHERE id = ?")
+            .bind(locked_until)
+            .bind(acc_id)
+            .execute(pool)
+            .await?;
+        warn!("Locked account {username} after {failed_logins} failed logins");
+        return Ok(LoginOutcome::Locked);
+    }
+
+    sqlx::query("UPDATE users SET failed_logins = ? WHERE id = ?")
+        .bind(failed_logins as i64)
+        .bind(acc_id)
+        .execute(pool)
+        .await?;
+    Ok(LoginOutcome::Failed(failed_logins))
+}
+
 fn on_login_command(
     mut events: EventReader<ExecuteCommandEvent>,
     mut commands: Commands,
-    mut query: Query<&mut DialogueRunner>,
+    settings: Res<LoginSettings>,
+    mut query: Query<(&mut DialogueRunner, &Connection)>,
 ) -> Result {
     for event in events.read() {
         if event.command.name != "login" {
@@ -76,40 +323,73 @@ fn on_login_command(
         let conn = event.source;
 
         let finished = Arc::new(RwLock::new(false));
-        let mut runner = query.get_mut(event.source)?;
+        let (mut runner, connection) = query.get_mut(event.source)?;
+        let peer_addr = connection.peer_addr().ip();
 
         runner.add_command_task(Box::new(Arc::clone(&finished)));
 
+        let settings = settings.clone();
         commands.run_sql(
             async move |pool| {
-                let Ok((acc_id, hash)): Result<(u64, Vec<u8>), sqlx::Error> =
-                    sqlx::query_as("SELECT id, password FROM users WHERE username = ?")
-                        .bind(&username)
-                        .fetch_one(&pool)
-                        .await
-                else {
-                    return Ok((u64::MAX, username, "".to_string(), "".to_string(), conn));
+                let outcome = match check_login(&pool, &settings, &username, &password).await {
+                    Ok(outcome) => outcome,
+                    Err(err) => {
+                        warn!("SQL query failed: {err}");
+                        LoginOutcome::SQLError
+                    }
+                };
+
+                let account = match outcome {
+                    LoginOutcome::Success(acc_id, _) => Some(acc_id),
+                    _ => None,
                 };
-                let hash = String::from_utf8(hash)?;
-                Ok((acc_id, username, password, hash, conn))
+                audit_login(
+                    &pool,
+                    account,
+                    &username,
+                    &peer_addr.to_string(),
+                    account.is_some(),
+                )
+                .await;
+
+                Ok((username, outcome))
             },
-            move |In((acc_id, username, password, hash, conn)): In<(
-                u64,
-                String,
-                String,
-                String,
-                Entity,
-            )>,
-                  mut commands: Commands|
+            move |In((username, outcome)): In<(String, LoginOutcome)>,
+                  mut commands: Commands,
+                  settings: Res<LoginSettings>,
+                  mut throttle: ResMut<LoginThrottle>,
+                  mut query: Query<&mut DialogueRunner, With<Connection>>|
                   -> Result {
-                if hash != "" {
-                    if let Ok(true) = bcrypt::verify(password, &hash) {
+                let mut runner = query.get_mut(conn)?;
+
+                let (error, failures) = match outcome {
+                    LoginOutcome::Success(acc_id, role) => {
                         commands
                             .entity(conn)
-                            .insert((LoggedIn(acc_id), Username(username)));
+                            .insert((LoggedIn(acc_id), Username(username), role));
+                        ("", 0)
                     }
+                    LoginOutcome::Failed(failures) => ("LoginFailed", failures),
+                    LoginOutcome::Locked => ("Locked", 0),
+                    LoginOutcome::SQLError => ("Error", 0),
+                };
+                set_error(&mut runner, error)?;
+
+                if error.is_empty() || error == "Error" {
+                    *finished.write().map_err(|_| "Poisoned RwLock")? = true;
+                    return Ok(());
                 }
-                *finished.write().map_err(|_| "Poisoned RwLock")? = true;
+
+                // Slow down guessing, both for the account and for the address
+                delay_failure(
+                    &mut commands,
+                    &settings,
+                    &mut throttle,
+                    conn,
+                    peer_addr,
+                    failures,
+                    Arc::clone(&finished),
+                );
                 Ok(())
             },
         );
@@ -117,6 +397,26 @@ fn on_login_command(
     Ok(())
 }
 
+fn finish_login_delays(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<LoginSettings>,
+    mut throttle: ResMut<LoginThrottle>,
+    mut query: Query<(Entity, &mut LoginDelay)>,
+) -> Result {
+    for (conn, mut delay) in &mut query {
+        if !delay.timer.tick(time.delta()).finished() {
+            continue;
+        }
+
+        *delay.finished.write().map_err(|_| "Poisoned RwLock")? = true;
+        commands.entity(conn).remove::<LoginDelay>();
+    }
+
+    throttle.forget_expired(settings.address_failure_window);
+    Ok(())
+}
+
 struct RegistrationResult {
     error: RegistrationError,
     entity: Entity,
@@ -138,6 +438,8 @@ impl From<sqlx::Error> for RegistrationError {
 fn on_register_account_command(
     mut events: EventReader<ExecuteCommandEvent>,
     mut commands: Commands,
+    settings: Res<LoginSettings>,
+    policies: Res<NamePolicies>,
     mut query: Query<&mut DialogueRunner>,
 ) -> Result {
     for event in events.read() {
@@ -148,19 +450,30 @@ fn on_register_account_command(
         let conn = event.source;
         let username = String::from(&event.command.parameters[0]);
         let password = String::from(&event.command.parameters[1]);
+        let cost = settings.password_cost;
 
-        let finished = Arc::new(RwLock::new(false));
         let mut runner = query.get_mut(event.source)?;
+        let username = match policies.username.check(&username) {
+            Ok(username) => username,
+            Err(rejection) => {
+                set_rejection(&mut runner, &rejection)?;
+                continue;
+            }
+        };
+
+        let finished = Arc::new(RwLock::new(false));
         runner.add_command_task(Box::new(Arc::clone(&finished)));
 
         commands.run_sql(
             async move |pool| {
-                let exists: bool = match sqlx::query_scalar(
-                    "SELECT EXISTS (SELECT 1 FROM users WHERE username = ?)",
-                )
-                .bind(&username)
-                .fetch_one(&pool)
-                .await
+                let sql = format!(
+                    "SELECT EXISTS (SELECT 1 FROM users WHERE {})",
+                    name_equals(&pool, "username")
+                );
+                let exists: i64 = match sqlx::query_scalar(&sql)
+                    .bind(&username)
+                    .fetch_one(&pool)
+                    .await
                 {
                     Ok(x) => x,
                     Err(err) => {
@@ -172,7 +485,7 @@ fn on_register_account_command(
                     }
                 };
 
-                if exists {
+                if exists != 0 {
                     return Ok(RegistrationResult {
                         error: RegistrationError::AccountExists,
                         entity: conn,
@@ -180,13 +493,35 @@ fn on_register_account_command(
                     });
                 }
 
+                // Errors have to end up in the result, or the dialogue would wait for it forever
+                let hash = match bcrypt::hash(&password, cost) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        warn!("Could not hash password of {username}: {err}");
+                        return Ok(RegistrationResult {
+                            error: RegistrationError::SQLError,
+                            entity: conn,
+                            username,
+                        });
+                    }
+                };
+
                 let acc_id = match sqlx::query("INSERT INTO users(username, password) VALUES(?, ?)")
                     .bind(&username)
-                    .bind(bcrypt::hash(&password, bcrypt::DEFAULT_COST)?)
+                    .bind(hash)
                     .execute(&pool)
                     .await
                 {
-                    Ok(res) => res.last_insert_id(),
+                    Ok(res) => match res.last_insert_id() {
+                        Some(acc_id) => acc_id as u64,
+                        None => {
+                            return Ok(RegistrationResult {
+                                error: RegistrationError::SQLError,
+                                entity: conn,
+                                username,
+                            });
+                        }
+                    },
                     Err(err) => {
                         return Ok(RegistrationResult {
                             error: err.into(),
@@ -210,25 +545,20 @@ fn on_register_account_command(
 
                 match &result.error {
                     RegistrationError::AccountExists => {
-                        runner.variable_storage_mut().set(
-                            "$error".to_string(),
-                            YarnValue::String("AccountExists".to_string()),
-                        )?;
+                        set_error(&mut runner, "AccountExists")?;
                     }
                     RegistrationError::SQLError => {
-                        runner
-                            .variable_storage_mut()
-                            .set("$error".to_string(), YarnValue::String("Error".to_string()))?;
+                        set_error(&mut runner, "Error")?;
                     }
                     RegistrationError::Success(acc_id) => {
-                        runner
-                            .variable_storage_mut()
-                            .set("$error".to_string(), YarnValue::String("".to_string()))?;
+                        set_error(&mut runner, "")?;
 
                         let acc_id = *acc_id;
-                        commands
-                            .entity(result.entity)
-                            .insert((Username(result.0.username), LoggedIn(acc_id)));
+                        commands.entity(result.entity).insert((
+                            Username(result.0.username),
+                            LoggedIn(acc_id),
+                            Role::Player,
+                        ));
                     }
                 }
 
@@ -241,10 +571,32 @@ fn on_register_account_command(
     Ok(())
 }
 
+/// A row of the `characters` table
+#[derive(sqlx::FromRow)]
+struct CharacterRow {
+    id: i64,
+    name: String,
+    race: i64,
+    class: i64,
+    room: i64,
+    played: i64,
+}
+
+/// Finds a character by its 1-based position in the selection list, or by name
+fn find_character(chars: Vec<CharacterRow>, selection: &str) -> Option<CharacterRow> {
+    if let Ok(index) = selection.parse::<usize>() {
+        return chars.into_iter().nth(index.checked_sub(1)?);
+    }
+
+    chars
+        .into_iter()
+        .find(|char| char.name.eq_ignore_ascii_case(selection))
+}
+
 fn on_choose_char_command(
     mut events: EventReader<ExecuteCommandEvent>,
     mut commands: Commands,
-    mut query: Query<(&DialogueRunner, &LoggedIn)>,
+    mut query: Query<(&mut DialogueRunner, &LoggedIn)>,
 ) -> Result {
     for event in events.read() {
         if event.command.name != "choose_char" {
@@ -253,60 +605,174 @@ fn on_choose_char_command(
 
         let conn = event.source;
 
-        let (runner, acc_id) = query.get(conn)?;
+        let (mut runner, acc_id) = query.get_mut(conn)?;
         let acc_id = acc_id.0;
 
-        let Some(selection) = event.command.parameters.get(0) else {
-            // TODO: No selection
-            debug!("No selection");
-            return Ok(());
-        };
+        let selection = event
+            .command
+            .parameters
+            .first()
+            .map(|selection| String::from(selection).trim().to_string())
+            .unwrap_or_default();
 
-        let YarnValue::String(selection) = selection.clone() else {
-            // TODO: Not a string???
-            debug!("Not a string???");
-            return Ok(());
-        };
+        if selection.is_empty() {
+            set_error(&mut runner, "NoSelection")?;
+            continue;
+        }
 
-        let Ok(selection) = selection.trim().parse::<u32>() else {
-            // TODO: Nor parseable
-            debug!("Not parseable");
-            return Ok(());
-        };
+        let finished = Arc::new(RwLock::new(false));
+        runner.add_command_task(Box::new(Arc::clone(&finished)));
 
         commands.run_sql(
             async move |pool| {
-                Ok(
-                    sqlx::query_as(
-                        "SELECT name, race, class, room FROM characters WHERE account = ? ORDER BY id ASC LIMIT ?, 1",
-                    )
-                    .bind(acc_id)
-                    .bind(selection.saturating_sub(1))
-                    .fetch_one(&pool)
-                    .await
-                    .map_err(Into::<BevyError>::into)
+                let chars: Result<Vec<CharacterRow>, sqlx::Error> = sqlx::query_as(
+                    "SELECT id, name, race, class, room, played FROM characters
+                    WHERE account = ? ORDER BY id",
                 )
+                .bind(acc_id as i64)
+                .fetch_all(&pool)
+                .await;
+
+                Ok(match chars {
+                    Ok(chars) => Ok(find_character(chars, &selection)),
+                    Err(err) => {
+                        warn!("SQL query failed: {err}");
+                        Err(())
+                    }
+                })
             },
-            move |res: In<Result<(String, u64, u64, u64)>>, mut commands: Commands| {
-                let Ok((ref name, race, class, room)) = *res else {
-                    // TODO: Invalid character
-                    debug!("Invalid character");
-                    return;
+            move |res: In<Result<Option<CharacterRow>, ()>>,
+                  mut commands: Commands,
+                  config: Res<ServerConfig>,
+                  mut query: Query<&mut DialogueRunner, With<Connection>>,
+                  room_query: Query<&Id, With<Room>>,
+                  character_query: Query<(Entity, &Character, Has<LinkDead>)>,
+                  settings: Res<SessionSettings>,
+                  mut sender: EventWriter<SendMessageAction>|
+                  -> Result {
+                let mut runner = query.get_mut(conn)?;
+
+                let error = match *res {
+                    Err(()) => "Error",
+                    Ok(None) => "InvalidCharacter",
+                    Ok(Some(ref char)) => {
+                        let id = char.id as u64;
+                        let room_exists = |room: u64| room_query.iter().any(|id| id.0 == room);
+
+                        let existing = character_query.iter().find(|(_, chr, _)| chr.id == id);
+                        let playing = character_query
+                            .iter()
+                            .filter(|(_, chr, _)| chr.account == acc_id)
+                            .count();
+
+                        if let Some((character, _, true)) = existing {
+                            commands
+                                .trigger_targets(ReconnectAction { connection: conn }, character);
+                            ""
+                        } else if existing.is_some() {
+                            commands.entity(conn).insert(TakeoverCandidate(id));
+                            "AlreadyPlaying"
+                        } else if playing >= settings.max_characters_per_account {
+                            "TooManyCharacters"
+                        } else if let Some(room) = [char.room as u64, config.starting_room]
+                            .into_iter()
+                            .find(|&room| room_exists(room))
+                        {
+                            if room != char.room as u64 {
+                                sender.println(conn, "The room you left no longer exists.");
+                            }
+
+                            let character = commands
+                                .spawn((
+                                    Character {
+                                        id,
+                                        account: acc_id,
+                                    },
+                                    PlayTime(Duration::from_secs(char.played as u64)),
+                                    ControlledBy(conn),
+                                ))
+                                .id();
+
+                            commands.trigger_targets(
+                                CharacterLoginEvent {
+                                    id,
+                                    name: char.name.clone(),
+                                    account: acc_id,
+                                    class: char.class as u64,
+                                    race: char.race as u64,
+                                    room,
+                                },
+                                character,
+                            );
+                            ""
+                        } else {
+                            warn!(
+                                "Neither room {} nor the starting room exist, {} can not log in",
+                                char.room, char.name
+                            );
+                            "NoRoom"
+                        }
+                    }
                 };
 
-                commands.trigger_targets(CharacterLoginEvent {
-                    name: name.clone(),
-                    account: acc_id,
-                    class,
-                    race,
-                    room: room as u64,
-                }, conn);
+                set_error(&mut runner, error)?;
+                *finished.write().map_err(|_| "Poisoned RwLock")? = true;
+                Ok(())
             },
         );
     }
     Ok(())
 }
 
+/// Placed on a connection that chose a character which is already controlled by another connection
+#[derive(Component, Clone, Copy, Debug)]
+struct TakeoverCandidate(u64);
+
+fn on_take_over_session_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    mut commands: Commands,
+    mut query: Query<(&mut DialogueRunner, &LoggedIn, Option<&TakeoverCandidate>)>,
+    character_query: Query<(Entity, &Character, Option<&ControlledBy>)>,
+    mut sender: EventWriter<SendMessageAction>,
+    mut disconnect: EventWriter<DisconnectAction>,
+) -> Result {
+    for event in events.read() {
+        if event.command.name != "take_over_session" {
+            continue;
+        }
+
+        let conn = event.source;
+        let (mut runner, logged_in, candidate) = query.get_mut(conn)?;
+
+        // The character might have logged out in the meantime
+        let character = candidate.and_then(|candidate| {
+            character_query
+                .iter()
+                .find(|(_, chr, _)| chr.id == candidate.0 && chr.account == logged_in.0)
+        });
+        commands.entity(conn).remove::<TakeoverCandidate>();
+
+        let Some((character, _, controller)) = character else {
+            set_error(&mut runner, "NotPlaying")?;
+            continue;
+        };
+
+        if let Some(controller) = controller {
+            sender.println(
+                controller.0,
+                "Your character has been taken over by another connection.",
+            );
+            disconnect.write(DisconnectAction {
+                connection: controller.0,
+            });
+        }
+        commands.trigger_targets(ReconnectAction { connection: conn }, character);
+
+        set_error(&mut runner, "")?;
+    }
+    Ok(())
+}
+
 fn on_print_char_selection_command(
     mut events: EventReader<ExecuteCommandEvent>,
     mut commands: Commands,
@@ -333,7 +799,7 @@ fn on_print_char_selection_command(
                 let res = match sqlx::query_as(
                     "SELECT name, race, class FROM characters WHERE account = ? ORDER BY id",
                 )
-                .bind(acc_id)
+                .bind(acc_id as i64)
                 .fetch_all(&pool)
                 .await
                 {
@@ -346,7 +812,7 @@ fn on_print_char_selection_command(
                 };
                 Ok((conn, Some(res)))
             },
-            move |In((conn, chars)): In<(Entity, Option<Vec<(String, u64, u64)>>)>,
+            move |In((conn, chars)): In<(Entity, Option<Vec<(String, i64, i64)>>)>,
                   classes: Res<Classes>,
                   races: Res<Races>,
                   mut sender: EventWriter<SendMessageAction>,
@@ -355,20 +821,16 @@ fn on_print_char_selection_command(
                 let mut runner = query.get_mut(conn)?;
 
                 let Some(chars) = chars else {
-                    runner
-                        .variable_storage_mut()
-                        .set("$error".to_string(), "Error".into())?;
+                    set_error(&mut runner, "Error")?;
                     *finished.write().map_err(|_| "Poisoned RwLock")? = true;
                     return Ok(());
                 };
-                runner
-                    .variable_storage_mut()
-                    .set("$error".to_string(), "".into())?;
+                set_error(&mut runner, "")?;
 
                 for (i, char) in chars.iter().enumerate() {
                     let name = &char.0;
-                    let race = races.get_race(char.1);
-                    let class = classes.get_class(char.2);
+                    let race = races.get_race(char.1 as u64);
+                    let class = classes.get_class(char.2 as u64);
 
                     sender.print(conn, &format!("{}: ", i + 1));
                     sender.print(conn, name);
@@ -402,3 +864,10 @@ impl Username {
 
 #[derive(Component, Reflect)]
 pub struct LoggedIn(u64);
+
+impl LoggedIn {
+    /// Id of the account in the `users` table
+    pub fn account(&self) -> u64 {
+        self.0
+    }
+}
@@ -1,11 +1,17 @@
+//! Yarn commands used by the character creation dialogue in `char_creation.yarn`
+use std::sync::{Arc, RwLock};
+
 use bevy::prelude::*;
+use bevy_yarnspinner::{events::ExecuteCommandEvent, prelude::*};
 
 use crate::{
-    auth::Username,
-    char::{Class, Race},
-    database::DatabaseCommandsEx,
-    telnet::{EventWriterTelnetEx, MessageReceived, SendMessageAction},
-    util::EntityCommandsEx,
+    auth::LoggedIn,
+    class::Classes,
+    config::ServerConfig,
+    database::{DatabaseCommandsEx, name_equals},
+    name_policy::{NamePolicies, set_error, set_rejection},
+    race::Races,
+    telnet::{Connection, EventWriterTelnetEx, SendMessageAction},
 };
 
 pub struct CharCreationPlugin;
@@ -15,330 +21,269 @@ impl Plugin for CharCreationPlugin {
         app.add_systems(
             Update,
             (
-                intro,
-                choose_username,
-                choose_password,
-                choose_race,
-                choose_class,
-                show_menu,
-            ),
+                on_check_char_name_command,
+                on_print_races_command,
+                on_choose_race_command,
+                on_print_classes_command,
+                on_choose_class_command,
+                on_create_character_command,
+            )
+                .after(YarnSpinnerSystemSet),
         );
     }
 }
 
-#[derive(Component, Debug, Default, PartialEq, Eq)]
-#[require(CharCreation)]
-pub enum CharCreationState {
-    #[default]
-    Intro,
-    Username,
-    Password,
-    Race,
-    Class,
-    Menu,
-}
-
+/// The character being created on a connection
 #[derive(Component, Debug, Default)]
 struct CharCreation {
-    pub username: Option<Username>,
-    pub password: Option<String>,
-    pub race: Option<Race>,
-    pub class: Option<Class>,
+    name: Option<String>,
+    race: Option<u64>,
+    class: Option<u64>,
 }
 
-impl CharCreation {
-    fn is_complete(&self) -> bool {
-        self.username.is_some()
-            && self.password.is_some()
-            && self.race.is_some()
-            && self.class.is_some()
-    }
-}
+/// Finds an entry by its 1-based position in `names`, or by name
+fn select<'a>(
+    selection: &str,
+    mut names: impl Iterator<Item = (u64, &'a str)>,
+) -> Option<(u64, &'a str)> {
+    let selection = selection.trim();
 
-fn intro(
-    mut query: Query<(Entity, &mut CharCreationState), Added<CharCreationState>>,
-    mut sender: EventWriter<SendMessageAction>,
-) {
-    for (ent, mut state) in &mut query {
-        sender.println(ent, "Welcome!");
-        *state = CharCreationState::Username;
+    if let Ok(index) = selection.parse::<usize>() {
+        return names.nth(index.checked_sub(1)?);
     }
+
+    names.find(|(_, name)| name.eq_ignore_ascii_case(selection))
 }
 
-fn choose_username(
+fn on_check_char_name_command(
+    mut events: EventReader<ExecuteCommandEvent>,
     mut commands: Commands,
-    mut query: Query<(Entity, &mut CharCreationState), Changed<CharCreationState>>,
-    mut sender: EventWriter<SendMessageAction>,
-) {
-    for (ent, state) in &mut query {
-        if *state != CharCreationState::Username {
+    policies: Res<NamePolicies>,
+    mut query: Query<&mut DialogueRunner>,
+) -> Result {
+    for event in events.read() {
+        if event.command.name != "check_char_name" {
             continue;
         }
 
-        sender.println(ent, "");
-        sender.println(ent, "First, choose a username.");
-        sender.print(ent, "Username: ");
-        sender.ga(ent);
-
-        commands.entity(ent).observe(
-            |trigger: Trigger<MessageReceived>,
-             mut commands: Commands,
-             mut query: Query<(&mut CharCreation, &mut CharCreationState)>| {
-                if let Ok((mut char, mut state)) = query.get_mut(trigger.target()) {
-                    char.username = Some(Username(trigger.event().to_text()));
-                    if char.is_complete() {
-                        *state = CharCreationState::Menu
-                    } else {
-                        *state = CharCreationState::Password
+        let conn = event.source;
+        let name = String::from(&event.command.parameters[0]);
+        let mut runner = query.get_mut(conn)?;
+
+        let name = match policies.character.check(&name) {
+            Ok(name) => name,
+            Err(rejection) => {
+                set_rejection(&mut runner, &rejection)?;
+                continue;
+            }
+        };
+
+        let finished = Arc::new(RwLock::new(false));
+        runner.add_command_task(Box::new(Arc::clone(&finished)));
+
+        commands.run_sql(
+            async move |pool| {
+                let sql = format!(
+                    "SELECT EXISTS (SELECT 1 FROM characters WHERE {})",
+                    name_equals(&pool, "name")
+                );
+                let taken: Result<i64, sqlx::Error> =
+                    sqlx::query_scalar(&sql).bind(&name).fetch_one(&pool).await;
+                Ok((name, taken.map(|taken| taken != 0).ok()))
+            },
+            move |In((name, taken)): In<(String, Option<bool>)>,
+                  mut commands: Commands,
+                  mut query: Query<&mut DialogueRunner, With<Connection>>|
+                  -> Result {
+                let mut runner = query.get_mut(conn)?;
+
+                match taken {
+                    None => set_error(&mut runner, "Error")?,
+                    Some(true) => set_error(&mut runner, "NameTaken")?,
+                    Some(false) => {
+                        set_error(&mut runner, "")?;
+                        runner
+                            .variable_storage_mut()
+                            .set("$system_charcreation_name".to_string(), name.clone().into())?;
+                        commands.entity(conn).insert(CharCreation {
+                            name: Some(name),
+                            ..default()
+                        });
                     }
                 }
-                commands.entity(trigger.observer()).despawn();
+
+                *finished.write().map_err(|_| "Poisoned RwLock")? = true;
+                Ok(())
             },
         );
     }
+    Ok(())
 }
 
-fn choose_password(
-    mut commands: Commands,
-    query: Query<(Entity, &CharCreationState), Changed<CharCreationState>>,
+fn on_print_races_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    races: Res<Races>,
     mut sender: EventWriter<SendMessageAction>,
 ) {
-    for (ent, state) in &query {
-        if *state != CharCreationState::Password {
+    for event in events.read() {
+        if event.command.name != "print_races" {
             continue;
         }
 
-        sender.println(ent, "");
-        sender.print(ent, "Password: ");
-        sender.echo(ent, false);
-        sender.ga(ent);
-
-        commands.entity(ent).observe_once(
-            |trigger: Trigger<MessageReceived>,
-             mut commands: Commands,
-             mut sender: EventWriter<SendMessageAction>| {
-                sender.println(trigger.target(), "");
-                sender.print(trigger.target(), "Confirm password: ");
-                sender.ga(trigger.target());
-
-                let password = trigger.event().to_text();
-
-                commands.entity(trigger.target()).observe_once(
-                    move |trigger: Trigger<MessageReceived>,
-                          mut query: Query<(&mut CharCreation, &mut CharCreationState)>,
-                          mut sender: EventWriter<SendMessageAction>| {
-                        let confirm = trigger.event().to_text();
-
-                        if let Ok((mut char, mut state)) = query.get_mut(trigger.target()) {
-                            if password != confirm {
-                                sender.println(trigger.target(), "");
-                                sender.println(trigger.target(), "Passwords do not match.");
-                                *state = CharCreationState::Password;
-                            } else {
-                                char.password = Some(password.clone());
-                                if char.is_complete() {
-                                    *state = CharCreationState::Menu;
-                                } else {
-                                    *state = CharCreationState::Race;
-                                }
-                                sender.println(trigger.target(), "");
-                                sender.echo(trigger.target(), true);
-                            }
-                        }
-                    },
-                );
-            },
-        );
+        for (i, race) in races.iter().enumerate() {
+            sender.println(event.source, &format!("{}: {}", i + 1, race.name));
+        }
     }
 }
 
-fn choose_race(
-    mut commands: Commands,
-    query: Query<(Entity, &CharCreationState), Changed<CharCreationState>>,
-    mut sender: EventWriter<SendMessageAction>,
-) {
-    for (ent, state) in &query {
-        if *state != CharCreationState::Race {
+fn on_choose_race_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    races: Res<Races>,
+    mut query: Query<(&mut DialogueRunner, &mut CharCreation)>,
+) -> Result {
+    for event in events.read() {
+        if event.command.name != "choose_race" {
             continue;
         }
 
-        sender.println(ent, "");
-        sender.println(ent, "You must now choose a race.");
-        sender.println(ent, "The following options are available:");
-        sender.println(ent, "- Human\r\n- Elf\r\n- Orc\r\n- Pixie");
-        sender.print(ent, "Race: ");
-        sender.ga(ent);
-
-        commands.entity(ent).observe_once(
-            move |trigger: Trigger<MessageReceived>,
-                  mut query: Query<(&mut CharCreation, &mut CharCreationState)>| {
-                debug!("Received race input");
-                let input = trigger.event().to_text();
-                let race = match input.to_lowercase().as_str() {
-                    "human" => Race::Human,
-                    "elf" => Race::Elf,
-                    "orc" => Race::Orc,
-                    "pixie" => Race::Pixie,
-                    _ => {
-                        trace!("Invalid race");
-                        if let Ok((_, mut state)) = query.get_mut(ent) {
-                            *state = CharCreationState::Race;
-                        }
-                        return;
-                    }
-                };
-                if let Ok((mut char, mut state)) = query.get_mut(ent) {
-                    char.race = Some(race);
-                    if char.is_complete() {
-                        *state = CharCreationState::Menu;
-                    } else {
-                        *state = CharCreationState::Class;
-                    }
-                }
-            },
-        );
+        let (mut runner, mut creation) = query.get_mut(event.source)?;
+        let selection = String::from(&event.command.parameters[0]);
+
+        let options = races.iter().map(|race| (race.id, race.name.as_str()));
+        let Some((id, name)) = select(&selection, options) else {
+            set_error(&mut runner, "InvalidRace")?;
+            continue;
+        };
+
+        creation.race = Some(id);
+        set_error(&mut runner, "")?;
+        runner
+            .variable_storage_mut()
+            .set("$system_charcreation_race".to_string(), name.into())?;
     }
+    Ok(())
 }
 
-fn choose_class(
-    mut commands: Commands,
-    query: Query<(Entity, &CharCreationState), Changed<CharCreationState>>,
+fn on_print_classes_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    classes: Res<Classes>,
     mut sender: EventWriter<SendMessageAction>,
 ) {
-    for (ent, state) in &query {
-        if *state != CharCreationState::Class {
+    for event in events.read() {
+        if event.command.name != "print_classes" {
             continue;
         }
 
-        sender.println(ent, "");
-        sender.println(ent, "You must now choose a class.");
-        sender.println(ent, "The following options are available:");
-        sender.println(ent, "- Warrior\r\n- Mage\r\n- Cleric");
-        sender.print(ent, "Class: ");
-        sender.ga(ent);
-
-        commands.entity(ent).observe_once(
-            move |trigger: Trigger<MessageReceived>,
-                  mut query: Query<(&mut CharCreation, &mut CharCreationState)>| {
-                let input = trigger.event().to_text();
-                let class = match input.to_lowercase().as_str() {
-                    "warrior" => Class::Warrior,
-                    "mage" => Class::Mage,
-                    "cleric" => Class::Cleric,
-                    _ => {
-                        if let Ok((_, mut state)) = query.get_mut(ent) {
-                            *state = CharCreationState::Class;
-                        }
-                        return;
-                    }
-                };
-                if let Ok((mut char, mut state)) = query.get_mut(ent) {
-                    char.class = Some(class);
-                    *state = CharCreationState::Menu;
-                }
-            },
-        );
+        for (i, class) in classes.iter().enumerate() {
+            sender.println(event.source, &format!("{}: {}", i + 1, class.name));
+        }
+    }
+}
+
+fn on_choose_class_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    classes: Res<Classes>,
+    mut query: Query<(&mut DialogueRunner, &mut CharCreation)>,
+) -> Result {
+    for event in events.read() {
+        if event.command.name != "choose_class" {
+            continue;
+        }
+
+        let (mut runner, mut creation) = query.get_mut(event.source)?;
+        let selection = String::from(&event.command.parameters[0]);
+
+        let options = classes.iter().map(|class| (class.id, class.name.as_str()));
+        let Some((id, name)) = select(&selection, options) else {
+            set_error(&mut runner, "InvalidClass")?;
+            continue;
+        };
+
+        creation.class = Some(id);
+        set_error(&mut runner, "")?;
+        runner
+            .variable_storage_mut()
+            .set("$system_charcreation_class".to_string(), name.into())?;
     }
+    Ok(())
+}
+
+enum CreationResult {
+    Success,
+    NameTaken,
+    SQLError,
 }
 
-fn show_menu(
+fn on_create_character_command(
+    mut events: EventReader<ExecuteCommandEvent>,
     mut commands: Commands,
-    query: Query<(Entity, &CharCreation, &CharCreationState), Changed<CharCreationState>>,
-    mut sender: EventWriter<SendMessageAction>,
+    config: Res<ServerConfig>,
+    mut query: Query<(&mut DialogueRunner, &CharCreation, &LoggedIn)>,
 ) -> Result {
-    for (ent, chr, state) in &query {
-        if *state != CharCreationState::Menu {
+    for event in events.read() {
+        if event.command.name != "create_character" {
             continue;
         }
 
-        let username = chr
-            .username
-            .as_ref()
-            .map(Username::as_str)
-            .ok_or("No username on char creation menu")?;
-
-        let password = chr
-            .password
-            .as_ref()
-            .ok_or("No password on char creation menu")?;
-
-        let race = chr
-            .race
-            .as_ref()
-            .map(Race::as_str)
-            .ok_or("No race on char creation menu")?;
-
-        let class = chr
-            .class
-            .as_ref()
-            .map(Class::as_str)
-            .ok_or("No class on char creation menu")?;
-
-        sender.println(ent, "");
-        sender.println(ent, &format!("Username: {}", username));
-        sender.println(ent, "Password: ***");
-        sender.println(ent, &format!("Race: {}", race));
-        sender.println(ent, &format!("Class: {}", class));
-
-        sender.println(ent, "");
-        sender.println(ent, "Enter CONFIRM to continue, USERNAME, PASSWORD, RACE, or CLASS to edit the respective field.");
-        sender.ga(ent);
-
-        commands.entity(ent).observe_once(
-            move |trigger: Trigger<MessageReceived>,
+        let conn = event.source;
+        let (mut runner, creation, logged_in) = query.get_mut(conn)?;
+
+        let (Some(name), Some(race), Some(class)) =
+            (creation.name.clone(), creation.race, creation.class)
+        else {
+            set_error(&mut runner, "Incomplete")?;
+            continue;
+        };
+        let account = logged_in.account();
+        let room = config.starting_room;
+
+        let finished = Arc::new(RwLock::new(false));
+        runner.add_command_task(Box::new(Arc::clone(&finished)));
+
+        commands.run_sql(
+            async move |pool| {
+                let result = sqlx::query(
+                    "INSERT INTO characters (account, name, race, class, room) VALUES (?, ?, ?, ?, ?)",
+                )
+                .bind(account as i64)
+                .bind(&name)
+                .bind(race as i64)
+                .bind(class as i64)
+                .bind(room as i64)
+                .execute(&pool)
+                .await;
+
+                Ok(match result {
+                    Ok(_) => CreationResult::Success,
+                    Err(err)
+                        if err
+                            .as_database_error()
+                            .is_some_and(|err| err.is_unique_violation()) =>
+                    {
+                        CreationResult::NameTaken
+                    }
+                    Err(err) => {
+                        warn!("Character creation failed: {}", err);
+                        CreationResult::SQLError
+                    }
+                })
+            },
+            move |result: In<CreationResult>,
                   mut commands: Commands,
-                  mut query: Query<(&mut CharCreationState, &CharCreation)>| -> Result {
-                if let Ok((mut state, chr)) = query.get_mut(ent) {
-                    let input = trigger.event().to_text();
-                    match input.to_lowercase().as_str() {
-                        "username" => *state = CharCreationState::Username,
-                        "password" => *state = CharCreationState::Password,
-                        "race" => *state = CharCreationState::Race,
-                        "class" => *state = CharCreationState::Class,
-                        "confirm" => {
-                            let username = chr
-                                .username
-                                .as_ref()
-                                .map(Username::as_str)
-                                .ok_or("No username on char creation menu")?
-                                .to_string();
-
-                            let password = chr
-                                .password
-                                .as_ref()
-                                .ok_or("No password on char creation menu")?
-                                .to_string();
-
-                            let race = chr
-                                .race
-                                .as_ref()
-                                .map(Race::as_str)
-                                .ok_or("No race on char creation menu")?
-                                .to_string();
-
-                            let class = chr
-                                .class
-                                .as_ref()
-                                .map(Class::as_str)
-                                .ok_or("No class on char creation menu")?
-                                .to_string();
-                            commands.run_sql(async move |pool| {
-                                let hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
-                                let result = sqlx::query("INSERT INTO users (username, password, race, class) VALUES (?, ?, ?, ?)")
-                                    .bind(username)
-                                    .bind(hash)
-                                    .bind(race)
-                                    .bind(class)
-                                    .execute(&pool).await;
-                                if let Err(err) = result {
-                                    warn!("Character creation failed: {}", err);
-                                }
-                                Ok(())
-                            }, |_: In<()>| {});
-                        }
-                        _ => *state = CharCreationState::Menu,
+                  mut query: Query<&mut DialogueRunner, With<Connection>>|
+                  -> Result {
+                let mut runner = query.get_mut(conn)?;
+
+                match *result {
+                    CreationResult::Success => {
+                        set_error(&mut runner, "")?;
+                        commands.entity(conn).remove::<CharCreation>();
                     }
+                    CreationResult::NameTaken => set_error(&mut runner, "NameTaken")?,
+                    CreationResult::SQLError => set_error(&mut runner, "Error")?,
                 }
+
+                *finished.write().map_err(|_| "Poisoned RwLock")? = true;
                 Ok(())
             },
         );
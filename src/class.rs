@@ -15,6 +15,7 @@ impl Plugin for ClassPlugin {
 
 #[derive(sqlx::FromRow, Clone, Reflect)]
 pub struct ClassDef {
+    #[sqlx(try_from = "i64")]
     pub id: u64,
     pub name: String,
 }
@@ -28,6 +29,10 @@ pub static PLACEHOLDER_CLASSDEF: LazyLock<ClassDef> = LazyLock::new(|| ClassDef
 pub struct Classes(Vec<ClassDef>);
 
 impl Classes {
+    pub fn iter(&self) -> impl Iterator<Item = &ClassDef> {
+        self.0.iter()
+    }
+
     pub fn get_class(&self, id: u64) -> &ClassDef {
         self.0
             .iter()
@@ -2,17 +2,27 @@ use bevy::{
     prelude::*,
     tasks::{IoTaskPool, Task, block_on},
 };
-use sqlx::{MySql, mysql::MySqlPoolOptions};
+use sqlx::{AnyPool, any::AnyPoolOptions, migrate::Migrator};
 
 use crate::util::FutureEx;
 
 pub struct DatabasePlugin {
     uri: String,
+    max_connections: u32,
 }
 
 impl DatabasePlugin {
     pub fn new(uri: String) -> Self {
-        DatabasePlugin { uri }
+        DatabasePlugin {
+            uri,
+            max_connections: 5,
+        }
+    }
+
+    /// Sets the size of the connection pool
+    pub fn max_connections(mut self, max_connections: u32) -> Self {
+        self.max_connections = max_connections;
+        self
     }
 }
 
@@ -23,6 +33,7 @@ impl Plugin for DatabasePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(DatabaseConfig {
             uri: self.uri.clone(),
+            max_connections: self.max_connections,
         });
         app.add_systems(PreStartup, setup.in_set(DatabaseSystemSet));
         app.add_systems(PreUpdate, sql_callbacks.in_set(DatabaseSystemSet));
@@ -32,10 +43,11 @@ impl Plugin for DatabasePlugin {
 #[derive(Resource)]
 struct DatabaseConfig {
     uri: String,
+    max_connections: u32,
 }
 
 #[derive(Resource, Deref)]
-struct SqlPool(sqlx::Pool<MySql>);
+struct SqlPool(AnyPool);
 
 #[derive(Component)]
 struct SqlTask(Option<Box<dyn RunSqlCallback + Send + Sync + 'static>>);
@@ -62,9 +74,14 @@ where
         };
 
         if task.is_finished() {
-            let Ok(output) = block_on(self.task.take().unwrap()) else {
-                // Failed, but done - despawn
-                return true;
+            let output = match block_on(self.task.take().unwrap()) {
+                Ok(output) => output,
+                Err(err) => {
+                    // Failed, but done - despawn. Callers that wait for the callback have to
+                    // turn errors into their output instead.
+                    warn!("SQL task failed, skipping its callback: {err}");
+                    return true;
+                }
             };
             self.callback.initialize(world);
             self.callback.run(output, world);
@@ -100,17 +117,66 @@ where
     }
 }
 
-async fn init_sqlx(uri: String) -> Result<sqlx::Pool<MySql>> {
-    let pool = MySqlPoolOptions::new()
-        .max_connections(5)
-        .connect(&uri)
-        .await?;
+static MYSQL_MIGRATIONS: Migrator = sqlx::migrate!("./migrations/mysql");
+static SQLITE_MIGRATIONS: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// Whether the URI points to an in-memory SQLite database
+fn is_in_memory(uri: &str) -> bool {
+    uri.starts_with("sqlite:") && (uri.contains(":memory:") || uri.contains("mode=memory"))
+}
+
+/// Lets SQLite create the database file if it doesn't exist yet
+fn connect_uri(uri: &str) -> String {
+    if !uri.starts_with("sqlite:") || is_in_memory(uri) || uri.contains("mode=") {
+        return uri.to_string();
+    }
+
+    let separator = if uri.contains('?') { '&' } else { '?' };
+    format!("{uri}{separator}mode=rwc")
+}
+
+/// A condition comparing `column` to a bound name regardless of case. MySQL's name columns use a
+/// case-insensitive collation, where `LOWER()` would only keep the comparison from using the
+/// unique index.
+pub fn name_equals(pool: &AnyPool, column: &str) -> String {
+    if pool.connect_options().database_url.scheme() == "sqlite" {
+        format!("LOWER({column}) = LOWER(?)")
+    } else {
+        format!("{column} = ?")
+    }
+}
+
+async fn init_sqlx(uri: String, max_connections: u32) -> Result<AnyPool> {
+    sqlx::any::install_default_drivers();
+
+    let options = if is_in_memory(&uri) {
+        // Every connection to an in-memory database gets its own database, so there may only
+        // ever be one, and it must never be closed
+        AnyPoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+    } else {
+        AnyPoolOptions::new().max_connections(max_connections)
+    };
+
+    let pool = options.connect(&connect_uri(&uri)).await?;
+
+    // Bring the schema up to date before anything else touches the database
+    let migrations = if uri.starts_with("sqlite:") {
+        &SQLITE_MIGRATIONS
+    } else {
+        &MYSQL_MIGRATIONS
+    };
+    migrations.run(&pool).await?;
 
     Ok(pool)
 }
 
 fn setup(mut commands: Commands, config: Res<DatabaseConfig>) -> Result {
-    let task = IoTaskPool::get().spawn(init_sqlx(config.uri.clone()).print_result());
+    let task = IoTaskPool::get()
+        .spawn(init_sqlx(config.uri.clone(), config.max_connections).print_result());
     let pool = block_on(task)?;
 
     commands.insert_resource(SqlPool(pool));
@@ -139,12 +205,14 @@ fn sql_callbacks(world: &mut World, task_query: &mut QueryState<(Entity, &mut Sq
 }
 
 pub trait DatabaseCommandsEx {
+    /// Runs `f` with the connection pool, then `callback` with its output. If `f` fails, the
+    /// error is logged and `callback` doesn't run.
     fn run_sql<F, Fut, Out, CBOut, CBMarker>(
         &mut self,
         f: F,
         callback: impl IntoSystem<In<Out>, CBOut, CBMarker> + Send + Sync + 'static,
     ) where
-        F: FnOnce(sqlx::Pool<MySql>) -> Fut,
+        F: FnOnce(AnyPool) -> Fut,
         F: Send + Sync + 'static,
         Fut: Future<Output = Result<Out>> + Send + 'static,
         Out: Send + Sync + 'static,
@@ -158,7 +226,7 @@ impl DatabaseCommandsEx for Commands<'_, '_> {
         f: F,
         callback: impl IntoSystem<In<Out>, CBOut, CBMarker> + Send + Sync + 'static,
     ) where
-        F: FnOnce(sqlx::Pool<MySql>) -> Fut,
+        F: FnOnce(AnyPool) -> Fut,
         F: Send + Sync + 'static,
         Fut: Future<Output = Result<Out>> + Send + 'static,
         Out: Send + Sync + 'static,
@@ -169,15 +237,7 @@ impl DatabaseCommandsEx for Commands<'_, '_> {
             let pool = world.resource::<SqlPool>();
             let fut = f(pool.0.clone());
 
-            let task = IoTaskPool::get().spawn(async move {
-                let output = fut.await;
-
-                if let Err(ref err) = output {
-                    warn!("SQL task failed: {}", err);
-                }
-
-                output
-            });
+            let task = IoTaskPool::get().spawn(fut);
 
             world.spawn(SqlTask(Some(Box::new(SqlTaskInner::new(task, callback)))));
         });
@@ -7,8 +7,8 @@ use bevy_yarnspinner::{
 };
 
 use crate::{
-    telnet::{EventWriterTelnetEx, MessageReceived, SendMessageAction},
-    util::When,
+    telnet::{EventWriterTelnetEx, MessageReceived, SendMessageAction, TerminalSize},
+    util::{When, word_wrap},
 };
 
 pub struct MenuPlugin;
@@ -58,10 +58,10 @@ fn on_enter_menu(
 fn on_present_line(
     mut events: EventReader<PresentLineEvent>,
     mut sender: EventWriter<SendMessageAction>,
-    mut query: Query<&mut DialogueRunner, With<InMenu>>,
+    mut query: Query<(&mut DialogueRunner, Option<&TerminalSize>), With<InMenu>>,
 ) -> Result {
     for event in events.read() {
-        let Ok(mut runner) = query.get_mut(event.source) else {
+        let Ok((mut runner, size)) = query.get_mut(event.source) else {
             continue;
         };
 
@@ -70,7 +70,8 @@ fn on_present_line(
             sender.print(event.source, " ");
             sender.ga(event.source);
         } else {
-            sender.println(event.source, &event.line.text);
+            let width = size.copied().unwrap_or_default().width;
+            sender.println(event.source, &word_wrap(&event.line.text, width.into()));
         }
         if !runner.is_waiting_for_option_selection() {
             runner.continue_in_next_update();
@@ -0,0 +1,143 @@
+//! MUD Server Status Protocol (https://tintin.mudhalla.net/protocols/mssp/)
+//!
+//! Crawlers either negotiate MSSP through telnet, or send `MSSP-REQUEST` at the login prompt and
+//! get the same data back as plain text.
+use bevy::prelude::*;
+use bevy_yarnspinner::{events::ExecuteCommandEvent, prelude::*};
+use libmudtelnet::telnet::{op_command, op_option};
+
+use crate::{
+    player_commands::Exploring,
+    telnet::{
+        DisconnectAction, EventWriterTelnetEx, NegotiationReceived, NewConnection,
+        SendMessageAction,
+    },
+    util::unix_time,
+    world::room::Room,
+};
+
+const MSSP_VAR: u8 = 1;
+const MSSP_VAL: u8 = 2;
+
+pub struct MsspPlugin;
+
+impl Plugin for MsspPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MsspSettings>()
+            .insert_resource(ServerStarted(unix_time()))
+            .add_systems(Update, offer_mssp)
+            .add_systems(Update, on_mssp_reply_command.after(YarnSpinnerSystemSet))
+            .add_observer(on_mssp_negotiation);
+    }
+}
+
+/// Values reported to MUD listing crawlers
+#[derive(Resource, Clone, Debug)]
+pub struct MsspSettings {
+    pub name: String,
+    /// Port the server can be reached on
+    pub port: u16,
+    /// Additional fields, like `CONTACT` or `WEBSITE`
+    pub extra: Vec<(String, String)>,
+}
+
+impl Default for MsspSettings {
+    fn default() -> Self {
+        Self {
+            name: "bevymud".to_string(),
+            port: 2222,
+            extra: Vec::new(),
+        }
+    }
+}
+
+/// Unix timestamp of when the server was started
+#[derive(Resource, Clone, Copy, Debug)]
+struct ServerStarted(i64);
+
+fn offer_mssp(
+    mut new_conn: EventReader<NewConnection>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    for conn in new_conn.read() {
+        sender.negotiate(conn.entity, op_command::WILL, op_option::MSSP);
+    }
+}
+
+fn on_mssp_negotiation(
+    trigger: Trigger<NegotiationReceived>,
+    settings: Res<MsspSettings>,
+    started: Res<ServerStarted>,
+    players: Query<(), With<Exploring>>,
+    rooms: Query<(), With<Room>>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    if trigger.option != op_option::MSSP || trigger.command != op_command::DO {
+        return;
+    }
+
+    let mut data = Vec::new();
+    for (var, val) in mssp_fields(
+        &settings,
+        *started,
+        players.iter().count(),
+        rooms.iter().count(),
+    ) {
+        data.push(MSSP_VAR);
+        data.extend_from_slice(var.as_bytes());
+        data.push(MSSP_VAL);
+        data.extend_from_slice(val.as_bytes());
+    }
+
+    sender.subnegotiate(trigger.target(), op_option::MSSP, &data);
+}
+
+/// Handles the `mssp_reply` yarn command, answering a plain-text `MSSP-REQUEST` and disconnecting
+fn on_mssp_reply_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    settings: Res<MsspSettings>,
+    started: Res<ServerStarted>,
+    players: Query<(), With<Exploring>>,
+    rooms: Query<(), With<Room>>,
+    mut sender: EventWriter<SendMessageAction>,
+    mut disconnect: EventWriter<DisconnectAction>,
+) {
+    for event in events.read() {
+        if event.command.name != "mssp_reply" {
+            continue;
+        }
+
+        let conn = event.source;
+
+        sender.println(conn, "MSSP-REPLY-START");
+        for (var, val) in mssp_fields(
+            &settings,
+            *started,
+            players.iter().count(),
+            rooms.iter().count(),
+        ) {
+            sender.println(conn, &format!("{var}\t{val}"));
+        }
+        sender.println(conn, "MSSP-REPLY-END");
+
+        disconnect.write(DisconnectAction { connection: conn });
+    }
+}
+
+fn mssp_fields(
+    settings: &MsspSettings,
+    started: ServerStarted,
+    players: usize,
+    rooms: usize,
+) -> Vec<(String, String)> {
+    let mut fields = vec![
+        ("NAME".to_string(), settings.name.clone()),
+        ("PLAYERS".to_string(), players.to_string()),
+        ("UPTIME".to_string(), started.0.to_string()),
+        ("ROOMS".to_string(), rooms.to_string()),
+        ("CODEBASE".to_string(), "bevymud".to_string()),
+        ("PORT".to_string(), settings.port.to_string()),
+    ];
+    fields.extend(settings.extra.iter().cloned());
+    fields
+}
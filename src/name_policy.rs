@@ -0,0 +1,241 @@
+//! Rules for account usernames and character names
+//!
+//! Both registration and character creation check names against a [`NamePolicy`]. A rejected
+//! name sets `$error` to `InvalidName` and `$error_reason` to an explanation for the player.
+use std::fmt::Display;
+
+use bevy::prelude::*;
+use bevy_yarnspinner::prelude::*;
+
+pub struct NamePolicyPlugin;
+
+impl Plugin for NamePolicyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NamePolicies>();
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct NamePolicies {
+    pub username: NamePolicy,
+    pub character: NamePolicy,
+}
+
+impl Default for NamePolicies {
+    fn default() -> Self {
+        Self {
+            username: NamePolicy {
+                min_length: 3,
+                max_length: 20,
+                allow_digits: true,
+                allowed_symbols: "_-".to_string(),
+                ..default()
+            },
+            character: NamePolicy {
+                min_length: 3,
+                max_length: 16,
+                capitalize: true,
+                ..default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NamePolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// ASCII letters are always allowed
+    pub allow_digits: bool,
+    /// Other characters that may appear, but not at the start of a name
+    pub allowed_symbols: String,
+    /// Names that can't be taken, compared case-insensitively
+    pub reserved: Vec<String>,
+    /// Words that may not appear anywhere in a name, compared case-insensitively
+    pub banned_words: Vec<String>,
+    /// Turn `bOB` into `Bob`
+    pub capitalize: bool,
+}
+
+impl Default for NamePolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 3,
+            max_length: 16,
+            allow_digits: false,
+            allowed_symbols: String::new(),
+            // Words the login and character selection prompts react to
+            reserved: ["new", "reset", "mssp-request", "admin", "system"]
+                .map(String::from)
+                .to_vec(),
+            banned_words: Vec::new(),
+            capitalize: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameRejection {
+    TooShort(usize),
+    TooLong(usize),
+    InvalidCharacter(char),
+    InvalidStart,
+    Reserved,
+    Banned,
+}
+
+impl Display for NameRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameRejection::TooShort(min) => {
+                write!(f, "Names must be at least {min} characters long.")
+            }
+            NameRejection::TooLong(max) => write!(f, "Names can be at most {max} characters long."),
+            NameRejection::InvalidCharacter(c) if c.is_whitespace() => {
+                write!(f, "Names can not contain spaces.")
+            }
+            NameRejection::InvalidCharacter(c) if c.is_control() => {
+                write!(f, "Names can not contain control characters.")
+            }
+            NameRejection::InvalidCharacter(c) => write!(f, "Names can not contain {c:?}."),
+            NameRejection::InvalidStart => write!(f, "Names must start with a letter."),
+            NameRejection::Reserved => write!(f, "That name is reserved."),
+            NameRejection::Banned => write!(f, "That name is not allowed."),
+        }
+    }
+}
+
+impl NamePolicy {
+    /// Checks `name` against the policy, and returns it in the form it should be stored in
+    pub fn check(&self, name: &str) -> Result<String, NameRejection> {
+        let name = name.trim();
+
+        if let Some(c) = name.chars().find(|&c| !self.allows(c)) {
+            return Err(NameRejection::InvalidCharacter(c));
+        }
+
+        // Only ASCII is allowed, so bytes are characters
+        if name.len() < self.min_length {
+            return Err(NameRejection::TooShort(self.min_length));
+        }
+        if name.len() > self.max_length {
+            return Err(NameRejection::TooLong(self.max_length));
+        }
+
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(NameRejection::InvalidStart);
+        }
+
+        let lower = name.to_lowercase();
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.to_lowercase() == lower)
+        {
+            return Err(NameRejection::Reserved);
+        }
+        if self
+            .banned_words
+            .iter()
+            .any(|word| lower.contains(&word.to_lowercase()))
+        {
+            return Err(NameRejection::Banned);
+        }
+
+        if !self.capitalize {
+            return Ok(name.to_string());
+        }
+
+        let mut normalized = name[..1].to_uppercase();
+        normalized.push_str(&lower[1..]);
+        Ok(normalized)
+    }
+
+    fn allows(&self, c: char) -> bool {
+        c.is_ascii_alphabetic()
+            || (self.allow_digits && c.is_ascii_digit())
+            || (c.is_ascii_graphic() && self.allowed_symbols.contains(c))
+    }
+}
+
+/// Sets `$error`, which dialogues check after a command, to `error`
+pub fn set_error(runner: &mut DialogueRunner, error: &str) -> Result {
+    runner
+        .variable_storage_mut()
+        .set("$error".to_string(), error.into())?;
+    Ok(())
+}
+
+/// Sets `$error` to `InvalidName` and `$error_reason` to why the name was rejected
+pub fn set_rejection(runner: &mut DialogueRunner, rejection: &NameRejection) -> Result {
+    set_error(runner, "InvalidName")?;
+    runner
+        .variable_storage_mut()
+        .set("$error_reason".to_string(), rejection.to_string().into())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_valid_names() {
+        let policy = NamePolicy::default();
+        assert_eq!(policy.check("  Bob "), Ok("Bob".to_string()));
+        assert_eq!(policy.check("bOB"), Ok("bOB".to_string()));
+    }
+
+    #[test]
+    fn checks_length() {
+        let policy = NamePolicy::default();
+        assert_eq!(policy.check("Al"), Err(NameRejection::TooShort(3)));
+        assert_eq!(
+            policy.check("Bartholomewtheelder"),
+            Err(NameRejection::TooLong(16))
+        );
+    }
+
+    #[test]
+    fn checks_characters() {
+        let mut policy = NamePolicy::default();
+        assert_eq!(
+            policy.check("Bob Smith"),
+            Err(NameRejection::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            policy.check("Bob2"),
+            Err(NameRejection::InvalidCharacter('2'))
+        );
+        assert_eq!(
+            policy.check("Zoë"),
+            Err(NameRejection::InvalidCharacter('ë'))
+        );
+
+        policy.allow_digits = true;
+        policy.allowed_symbols = "-".to_string();
+        assert_eq!(policy.check("Bob-2"), Ok("Bob-2".to_string()));
+        assert_eq!(policy.check("2Bob"), Err(NameRejection::InvalidStart));
+        assert_eq!(policy.check("-Bob"), Err(NameRejection::InvalidStart));
+    }
+
+    #[test]
+    fn rejects_reserved_and_banned_names() {
+        let mut policy = NamePolicy::default();
+        assert_eq!(policy.check("NEW"), Err(NameRejection::Reserved));
+
+        policy.banned_words = vec!["Darn".to_string()];
+        assert_eq!(policy.check("Darnbob"), Err(NameRejection::Banned));
+        assert_eq!(policy.check("BobDARN"), Err(NameRejection::Banned));
+    }
+
+    #[test]
+    fn capitalizes_names() {
+        let policy = NamePolicy {
+            capitalize: true,
+            ..default()
+        };
+        assert_eq!(policy.check("bOB"), Ok("Bob".to_string()));
+        assert_eq!(policy.check("alice"), Ok("Alice".to_string()));
+    }
+}
@@ -0,0 +1,340 @@
+//! Changing passwords, and resetting them with one-time codes issued by admins
+use std::sync::{Arc, RwLock};
+
+use bevy::prelude::*;
+use bevy_yarnspinner::{events::ExecuteCommandEvent, prelude::*};
+use rand::Rng;
+use sqlx::AnyPool;
+
+use crate::{
+    auth::{LoggedIn, LoginSettings, LoginThrottle, delay_failure},
+    database::{DatabaseCommandsEx, name_equals},
+    menu::EnterMenu,
+    name_policy::set_error,
+    permissions::Permission,
+    player_commands::{AppCommandRegistryEx, CommandDef, ExplorationCommandEvent, Exploring},
+    session::ControlledBy,
+    telnet::{Connection, EventWriterTelnetEx, SendMessageAction},
+    util::unix_time,
+};
+
+/// Characters used in reset codes, leaving out ones that are easily confused
+const RESET_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const RESET_CODE_LENGTH: usize = 12;
+
+pub struct PasswordPlugin;
+
+impl Plugin for PasswordPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_command(
+            CommandDef::new("password", "Change your password"),
+            password_command,
+        )
+        .add_command(
+            CommandDef::new("resetcode", "Issue a password reset code for an account")
+                .permission(Permission::ManageAccounts),
+            resetcode_command,
+        )
+        .add_systems(
+            Update,
+            (on_change_password_command, on_redeem_reset_code_command).after(YarnSpinnerSystemSet),
+        );
+    }
+}
+
+fn password_command(
+    trigger: Trigger<ExplorationCommandEvent>,
+    mut commands: Commands,
+    controller_query: Query<&ControlledBy>,
+) {
+    let character = trigger.character;
+    let Ok(controller) = controller_query.get(character) else {
+        return;
+    };
+
+    // Input goes to the menu until it returns to the game
+    commands.entity(character).remove::<Exploring>();
+    commands
+        .entity(controller.0)
+        .trigger(EnterMenu("System_Password_Start".to_string()));
+}
+
+enum ChangeResult {
+    Success,
+    WrongPassword,
+    Error,
+}
+
+async fn change_password(
+    pool: &AnyPool,
+    acc_id: u64,
+    old_password: &str,
+    new_password: &str,
+    cost: u32,
+) -> Result<ChangeResult> {
+    let hash: String = sqlx::query_scalar("SELECT password FROM users WHERE id = ?")
+        .bind(acc_id as i64)
+        .fetch_one(pool)
+        .await?;
+
+    if !bcrypt::verify(old_password, &hash).unwrap_or(false) {
+        return Ok(ChangeResult::WrongPassword);
+    }
+
+    sqlx::query("UPDATE users SET password = ? WHERE id = ?")
+        .bind(bcrypt::hash(new_password, cost)?)
+        .bind(acc_id as i64)
+        .execute(pool)
+        .await?;
+
+    Ok(ChangeResult::Success)
+}
+
+fn on_change_password_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    mut commands: Commands,
+    settings: Res<LoginSettings>,
+    mut query: Query<(&mut DialogueRunner, &LoggedIn, &Connection)>,
+) -> Result {
+    for event in events.read() {
+        if event.command.name != "change_password" {
+            continue;
+        }
+
+        let conn = event.source;
+        let old_password = String::from(&event.command.parameters[0]);
+        let new_password = String::from(&event.command.parameters[1]);
+        let cost = settings.password_cost;
+
+        let (mut runner, logged_in, connection) = query.get_mut(conn)?;
+        let acc_id = logged_in.account();
+        let peer_addr = connection.peer_addr().ip();
+
+        let finished = Arc::new(RwLock::new(false));
+        runner.add_command_task(Box::new(Arc::clone(&finished)));
+
+        commands.run_sql(
+            async move |pool| {
+                Ok(
+                    change_password(&pool, acc_id, &old_password, &new_password, cost)
+                        .await
+                        .unwrap_or_else(|err| {
+                            warn!("Could not change password: {err}");
+                            ChangeResult::Error
+                        }),
+                )
+            },
+            move |result: In<ChangeResult>,
+                  mut commands: Commands,
+                  settings: Res<LoginSettings>,
+                  mut throttle: ResMut<LoginThrottle>,
+                  mut query: Query<&mut DialogueRunner, With<Connection>>|
+                  -> Result {
+                let mut runner = query.get_mut(conn)?;
+
+                match *result {
+                    ChangeResult::Success => set_error(&mut runner, "")?,
+                    ChangeResult::WrongPassword => {
+                        // Guessing the old password is throttled like guessing it at login
+                        set_error(&mut runner, "WrongPassword")?;
+                        delay_failure(
+                            &mut commands,
+                            &settings,
+                            &mut throttle,
+                            conn,
+                            peer_addr,
+                            0,
+                            Arc::clone(&finished),
+                        );
+                        return Ok(());
+                    }
+                    ChangeResult::Error => set_error(&mut runner, "Error")?,
+                }
+
+                *finished.write().map_err(|_| "Poisoned RwLock")? = true;
+                Ok(())
+            },
+        );
+    }
+    Ok(())
+}
+
+fn generate_reset_code() -> String {
+    let mut rng = rand::rng();
+    (0..RESET_CODE_LENGTH)
+        .map(|_| RESET_CODE_ALPHABET[rng.random_range(0..RESET_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Stores a new reset code for `username`, returning false if the account doesn't exist
+async fn issue_reset_code(
+    pool: &AnyPool,
+    username: &str,
+    code: &str,
+    cost: u32,
+    expires_at: i64,
+) -> Result<bool> {
+    let sql = format!(
+        "SELECT id FROM users WHERE {}",
+        name_equals(pool, "username")
+    );
+    let acc_id: Option<i64> = sqlx::query_scalar(&sql)
+        .bind(username)
+        .fetch_optional(pool)
+        .await?;
+    let Some(acc_id) = acc_id else {
+        return Ok(false);
+    };
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE password_resets SET used_at = ? WHERE user_id = ? AND used_at IS NULL")
+        .bind(unix_time())
+        .bind(acc_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO password_resets (user_id, code_hash, expires_at) VALUES (?, ?, ?)")
+        .bind(acc_id)
+        .bind(bcrypt::hash(code, cost)?)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// `resetcode <username>` issues a code that lets the owner of the account choose a new password
+/// at the login prompt. Earlier codes for the account stop working.
+fn resetcode_command(
+    trigger: Trigger<ExplorationCommandEvent>,
+    mut commands: Commands,
+    settings: Res<LoginSettings>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    let character = trigger.character;
+    let Some(username) = trigger.args.first().cloned() else {
+        sender.println(character, "Usage: resetcode <username>");
+        return;
+    };
+
+    let code = generate_reset_code();
+    let cost = settings.password_cost;
+    let expires_at = unix_time() + settings.reset_code_lifetime.as_secs() as i64;
+
+    commands.run_sql(
+        async move |pool| {
+            let result = issue_reset_code(&pool, &username, &code, cost, expires_at).await;
+            Ok((username, result.map(|issued| issued.then_some(code))))
+        },
+        move |In((username, code)): In<(String, Result<Option<String>>)>,
+              mut sender: EventWriter<SendMessageAction>| {
+            match code {
+                Ok(Some(code)) => {
+                    info!("Issued a password reset code for {username}");
+                    sender.println(character, &format!("Reset code for {username}: {code}"));
+                }
+                Ok(None) => sender.println(character, &format!("There is no account {username}.")),
+                Err(err) => {
+                    warn!("Could not issue a reset code for {username}: {err}");
+                    sender.println(character, "Could not issue a reset code.");
+                }
+            }
+        },
+    );
+}
+
+/// Sets a new password if `code` is a valid reset code for `username`
+async fn redeem_reset_code(
+    pool: &AnyPool,
+    username: &str,
+    code: &str,
+    password: &str,
+    cost: u32,
+) -> Result<bool> {
+    let now = unix_time();
+    let sql = format!(
+        "SELECT password_resets.id, users.id, password_resets.code_hash
+        FROM password_resets JOIN users ON users.id = password_resets.user_id
+        WHERE {} AND password_resets.used_at IS NULL AND password_resets.expires_at > ?",
+        name_equals(pool, "users.username")
+    );
+    let resets: Vec<(i64, i64, String)> = sqlx::query_as(&sql)
+        .bind(username)
+        .bind(now)
+        .fetch_all(pool)
+        .await?;
+
+    let Some((reset_id, acc_id, _)) = resets
+        .into_iter()
+        .find(|(_, _, hash)| bcrypt::verify(code, hash).unwrap_or(false))
+    else {
+        return Ok(false);
+    };
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE password_resets SET used_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(reset_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE users SET password = ?, failed_logins = 0, locked_until = NULL WHERE id = ?",
+    )
+    .bind(bcrypt::hash(password, cost)?)
+    .bind(acc_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    info!("Password of {username} was reset");
+    Ok(true)
+}
+
+fn on_redeem_reset_code_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    mut commands: Commands,
+    settings: Res<LoginSettings>,
+    mut query: Query<&mut DialogueRunner>,
+) -> Result {
+    for event in events.read() {
+        if event.command.name != "redeem_reset_code" {
+            continue;
+        }
+
+        let conn = event.source;
+        let username = String::from(&event.command.parameters[0]);
+        let code = String::from(&event.command.parameters[1])
+            .trim()
+            .to_uppercase();
+        let password = String::from(&event.command.parameters[2]);
+        let cost = settings.password_cost;
+
+        let finished = Arc::new(RwLock::new(false));
+        let mut runner = query.get_mut(conn)?;
+        runner.add_command_task(Box::new(Arc::clone(&finished)));
+
+        commands.run_sql(
+            async move |pool| {
+                Ok(redeem_reset_code(&pool, &username, &code, &password, cost)
+                    .await
+                    .inspect_err(|err| warn!("Could not reset password of {username}: {err}"))
+                    .ok())
+            },
+            move |redeemed: In<Option<bool>>,
+                  mut query: Query<&mut DialogueRunner, With<Connection>>|
+                  -> Result {
+                let mut runner = query.get_mut(conn)?;
+
+                match *redeemed {
+                    Some(true) => set_error(&mut runner, "")?,
+                    Some(false) => set_error(&mut runner, "InvalidCode")?,
+                    None => set_error(&mut runner, "Error")?,
+                }
+
+                *finished.write().map_err(|_| "Poisoned RwLock")? = true;
+                Ok(())
+            },
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Account roles, and the permissions needed to run privileged commands
+//!
+//! Every account has a [`Role`] stored in the `role` column of `users`, which is placed on the
+//! connection when it logs in. Commands that need a [`Permission`] name it in their
+//! [`CommandDef`](crate::player_commands::CommandDef); characters without it get "Huh?" instead.
+use std::{fmt::Display, str::FromStr};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::session::ControlledBy;
+
+pub struct PermissionsPlugin;
+
+impl Plugin for PermissionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Role>();
+    }
+}
+
+/// Role of the account a connection is logged in to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Reflect, Component)]
+pub enum Role {
+    #[default]
+    Player,
+    Builder,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn has(self, permission: Permission) -> bool {
+        self >= permission.role()
+    }
+
+    /// Name of the role in the `users` table
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Player => "player",
+            Role::Builder => "builder",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "player" => Ok(Role::Player),
+            "builder" => Ok(Role::Builder),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            _ => Err(format!("Unknown role {s:?}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum Permission {
+    /// Inspect every entity in the world
+    Debug,
+    /// Issue password reset codes
+    ManageAccounts,
+}
+
+impl Permission {
+    /// The lowest role that has this permission
+    pub fn role(self) -> Role {
+        match self {
+            Permission::Debug | Permission::ManageAccounts => Role::Admin,
+        }
+    }
+}
+
+/// Checks what characters are allowed to do, based on the role of their connection
+#[derive(SystemParam)]
+pub struct Permissions<'w, 's> {
+    controller_query: Query<'w, 's, &'static ControlledBy>,
+    role_query: Query<'w, 's, &'static Role>,
+}
+
+impl Permissions<'_, '_> {
+    /// Role of the account controlling `character`
+    pub fn role(&self, character: Entity) -> Role {
+        let connection = self
+            .controller_query
+            .get(character)
+            .map(|controller| controller.0)
+            .unwrap_or(character);
+
+        self.role_query.get(connection).copied().unwrap_or_default()
+    }
+
+    pub fn has(&self, character: Entity, permission: Permission) -> bool {
+        self.role(character).has(permission)
+    }
+}
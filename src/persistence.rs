@@ -0,0 +1,175 @@
+//! Writes characters back to the `characters` table
+//!
+//! Characters are saved when they quit, when their connection is lost, when they are removed
+//! after being link-dead, and periodically. Saves are collected during the frame and written in a
+//! single transaction.
+use std::{collections::HashMap, time::Duration};
+
+use bevy::prelude::*;
+
+use crate::{
+    database::DatabaseCommandsEx,
+    misc::Id,
+    session::{Character, Controlling, LinkDead},
+    telnet::ConnectionClosed,
+    util::unix_time,
+    world::room::{InRoom, Room},
+};
+
+pub struct PersistencePlugin;
+
+impl Plugin for PersistencePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PlayTime>()
+            .init_resource::<PersistenceSettings>()
+            .init_resource::<PendingSaves>()
+            .add_observer(on_save_character_action)
+            .add_observer(on_connection_closed)
+            .add_systems(Startup, setup_autosave)
+            .add_systems(Update, (track_play_time, autosave).chain())
+            .add_systems(PostUpdate, flush_saves);
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct PersistenceSettings {
+    /// Time between saving every character in the world
+    pub autosave_interval: Duration,
+}
+
+impl Default for PersistenceSettings {
+    fn default() -> Self {
+        Self {
+            autosave_interval: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Save the target character at the end of the frame
+/// Event target is the character
+#[derive(Clone, Debug, Reflect, Event)]
+pub struct SaveCharacterAction;
+
+/// Time a character has spent in the world, over all its sessions
+#[derive(Clone, Copy, Debug, Default, Reflect, Component)]
+pub struct PlayTime(pub Duration);
+
+/// The persistent state of a character, taken when the save was requested
+#[derive(Clone, Debug)]
+struct CharacterSnapshot {
+    room: Option<u64>,
+    played: u64,
+    saved_at: i64,
+}
+
+/// Saves waiting to be written, by character id
+#[derive(Resource, Default)]
+struct PendingSaves(HashMap<u64, CharacterSnapshot>);
+
+#[derive(Resource, Deref, DerefMut)]
+struct AutosaveTimer(Timer);
+
+fn setup_autosave(mut commands: Commands, settings: Res<PersistenceSettings>) {
+    commands.insert_resource(AutosaveTimer(Timer::new(
+        settings.autosave_interval,
+        TimerMode::Repeating,
+    )));
+}
+
+fn snapshot(
+    room: Option<&InRoom>,
+    play_time: Option<&PlayTime>,
+    room_query: &Query<&Id, With<Room>>,
+) -> CharacterSnapshot {
+    CharacterSnapshot {
+        room: room
+            .and_then(|room| room_query.get(room.0).ok())
+            .map(|id| id.0),
+        played: play_time.map(|time| time.0.as_secs()).unwrap_or_default(),
+        saved_at: unix_time(),
+    }
+}
+
+/// Counts the time characters spend in the world, not counting time spent link-dead
+fn track_play_time(time: Res<Time>, mut query: Query<&mut PlayTime, Without<LinkDead>>) {
+    for mut play_time in &mut query {
+        play_time.0 += time.delta();
+    }
+}
+
+fn on_save_character_action(
+    trigger: Trigger<SaveCharacterAction>,
+    mut pending: ResMut<PendingSaves>,
+    character_query: Query<(&Character, Option<&InRoom>, Option<&PlayTime>)>,
+    room_query: Query<&Id, With<Room>>,
+) {
+    let Ok((character, room, play_time)) = character_query.get(trigger.target()) else {
+        return;
+    };
+
+    pending
+        .0
+        .insert(character.id, snapshot(room, play_time, &room_query));
+}
+
+fn on_connection_closed(
+    trigger: Trigger<ConnectionClosed>,
+    mut commands: Commands,
+    controlling_query: Query<&Controlling>,
+) {
+    if let Ok(controlling) = controlling_query.get(trigger.target()) {
+        commands.trigger_targets(SaveCharacterAction, controlling.character());
+    }
+}
+
+fn autosave(
+    time: Res<Time>,
+    mut timer: ResMut<AutosaveTimer>,
+    mut pending: ResMut<PendingSaves>,
+    character_query: Query<(&Character, Option<&InRoom>, Option<&PlayTime>)>,
+    room_query: Query<&Id, With<Room>>,
+) {
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    for (character, room, play_time) in &character_query {
+        pending
+            .0
+            .insert(character.id, snapshot(room, play_time, &room_query));
+    }
+}
+
+fn flush_saves(mut commands: Commands, mut pending: ResMut<PendingSaves>) {
+    if pending.0.is_empty() {
+        return;
+    }
+
+    let saves = std::mem::take(&mut pending.0);
+
+    commands.run_sql(
+        async move |pool| {
+            let mut tx = pool.begin().await?;
+
+            for (id, snapshot) in &saves {
+                // Characters that aren't in a room keep the one they were last saved in
+                sqlx::query(
+                    "UPDATE characters SET room = COALESCE(?, room), played = ?, last_seen = ?
+                    WHERE id = ?",
+                )
+                .bind(snapshot.room.map(|room| room as i64))
+                .bind(snapshot.played as i64)
+                .bind(snapshot.saved_at)
+                .bind(*id as i64)
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            tx.commit().await?;
+            Ok(saves.len())
+        },
+        |count: In<usize>| {
+            debug!("Saved {} characters", *count);
+        },
+    );
+}
@@ -1,58 +1,164 @@
 use bevy::prelude::*;
+use bevy_yarnspinner::{events::ExecuteCommandEvent, prelude::*};
 
-use crate::{auth::CharacterLoginEvent, telnet::MessageReceived};
+use crate::{
+    auth::CharacterLoginEvent,
+    permissions::Permissions,
+    session::Controlling,
+    telnet::{EventWriterTelnetEx, MessageReceived, SendMessageAction},
+};
+
+mod parser;
+mod registry;
+
+pub use parser::{TargetSpec, tokenize};
+pub use registry::{AppCommandRegistryEx, CommandDef, CommandRegistry};
 
 pub struct PlayerCommandsPlugin;
 
 impl Plugin for PlayerCommandsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(FixedUpdate, on_message_received)
-            .add_observer(test)
-            .add_observer(on_login);
+        app.init_resource::<CommandRegistry>()
+            .add_systems(FixedUpdate, on_message_received)
+            .add_systems(
+                Update,
+                on_return_to_game_command.after(YarnSpinnerSystemSet),
+            )
+            .add_observer(on_login)
+            .add_command(
+                CommandDef::new("help", "List commands, or explain one")
+                    .alias("?")
+                    .min_prefix(1),
+                help_command,
+            );
     }
 }
 
-fn test(trigger: Trigger<ExplorationCommandEvent>) {
-    println!("{:?}", *trigger);
-}
-
 /// A player that is in the world, doing normal movement, emotes, etc.
 /// (i.e. not in an editor or otherwise in a different "menu")
 #[derive(Copy, Clone, Debug, Reflect, Component)]
 pub struct Exploring;
 
+/// Triggered on the handler of a registered command, see [`AppCommandRegistryEx::add_command`]
 #[derive(Clone, Debug, Reflect, Event)]
 pub struct ExplorationCommandEvent {
+    /// The character that typed the command
+    pub character: Entity,
+    /// The full name of the command, even if it was abbreviated
     pub command: String,
+    /// The words after the command, see [`tokenize`]
     pub args: Vec<String>,
     pub line: String,
 }
 
+impl ExplorationCommandEvent {
+    /// The arguments as a target, like `2.sword` or `all.coin in bag`
+    pub fn parse_target(&self) -> Option<TargetSpec> {
+        TargetSpec::parse(&self.args)
+    }
+}
+
 fn on_login(trigger: Trigger<CharacterLoginEvent>, mut commands: Commands) {
     commands.entity(trigger.target()).insert(Exploring);
 }
 
+/// Lets the character controlled by a connection leave a menu and go back to exploring
+fn on_return_to_game_command(
+    mut events: EventReader<ExecuteCommandEvent>,
+    mut commands: Commands,
+    controlling_query: Query<&Controlling>,
+) {
+    for event in events.read() {
+        if event.command.name != "return_to_game" {
+            continue;
+        }
+
+        if let Ok(controlling) = controlling_query.get(event.source) {
+            commands.entity(controlling.character()).insert(Exploring);
+        }
+    }
+}
+
 fn on_message_received(
     mut events: EventReader<MessageReceived>,
-    query: Query<(), With<Exploring>>,
+    controlling_query: Query<&Controlling>,
+    character_query: Query<EntityRef>,
+    registry: Res<CommandRegistry>,
+    permissions: Permissions,
+    mut sender: EventWriter<SendMessageAction>,
     mut commands: Commands,
 ) {
     for event in events.read() {
-        if query.contains(event.connection) {
-            let line = event.to_text();
-            let mut split = line.split(" ");
-            let Some(command) = split.next() else {
-                continue;
-            };
-
-            commands.trigger_targets(
-                ExplorationCommandEvent {
-                    command: command.to_string(),
-                    args: split.map(ToString::to_string).collect(),
-                    line,
-                },
-                event.connection,
-            );
+        let Ok(controlling) = controlling_query.get(event.connection) else {
+            continue;
+        };
+        let character = controlling.character();
+        let Ok(entity) = character_query.get(character) else {
+            continue;
+        };
+
+        if !entity.contains::<Exploring>() {
+            continue;
+        }
+
+        let line = event.to_text();
+        let mut tokens = tokenize(&line).into_iter();
+        let Some(word) = tokens.next() else {
+            continue;
+        };
+
+        let Some((def, handler)) =
+            registry.resolve(&word, |def| def.permitted(&permissions, character))
+        else {
+            sender.println(character, "Huh? Type HELP for a list of commands.");
+            continue;
+        };
+
+        if !def.available(&entity) {
+            sender.println(character, "You can't do that right now.");
+            continue;
+        }
+
+        commands.trigger_targets(
+            ExplorationCommandEvent {
+                character,
+                command: def.name.to_string(),
+                args: tokens.collect(),
+                line,
+            },
+            handler,
+        );
+    }
+}
+
+/// `help` lists the commands the character can use, `help <command>` explains one of them
+fn help_command(
+    trigger: Trigger<ExplorationCommandEvent>,
+    registry: Res<CommandRegistry>,
+    permissions: Permissions,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    let character = trigger.character;
+    let permitted = |def: &CommandDef| def.permitted(&permissions, character);
+
+    let Some(word) = trigger.args.first() else {
+        let mut defs: Vec<_> = registry.iter().filter(|def| permitted(def)).collect();
+        defs.sort_by_key(|def| def.name);
+
+        sender.println(character, "Commands:");
+        for def in defs {
+            sender.println(character, &format!("  {:<12} {}", def.name, def.help));
         }
+        return;
+    };
+
+    let Some((def, _)) = registry.resolve(word, permitted) else {
+        sender.println(character, &format!("There is no command {word:?}."));
+        return;
+    };
+
+    sender.println(character, &format!("{}: {}", def.name, def.help));
+    if !def.aliases.is_empty() {
+        sender.println(character, &format!("Aliases: {}", def.aliases.join(", ")));
     }
 }
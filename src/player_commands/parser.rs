@@ -0,0 +1,272 @@
+//! Splitting command lines into words, and picking out the things a command refers to
+//!
+//! Targets use the usual MUD syntax: `sword` is the first thing called sword, `2.sword` the
+//! second one, `all.sword` every one of them, `all` everything, and `sword in bag` looks for
+//! the sword inside the bag.
+use bevy::prelude::*;
+
+use crate::world::room::RoomContents;
+
+/// Splits `line` into words. Runs of whitespace separate words, and text in single or double
+/// quotes is kept together. A quote only opens at the start of a word and only closes before
+/// whitespace or the end of the line, so apostrophes like in `bob's` stay part of the word. A
+/// missing closing quote ends the word at the end of the line.
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    // Whether `current` holds a word, which may be an empty quoted one
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q && chars.peek().is_none_or(|next| next.is_whitespace()) => {
+                quote = None;
+            }
+            Some(_) => current.push(c),
+            None if !in_word && (c == '"' || c == '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    tokens.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/// Which of the things matching a target are meant
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Selector {
+    /// The nth match, starting at 1
+    Nth(usize),
+    All,
+}
+
+/// A reference to one or more things, like `2.sword` or `all.coin in bag`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetSpec {
+    pub selector: Selector,
+    /// Keywords that must all match a word of the name. Empty for a plain `all`.
+    pub keywords: Vec<String>,
+    /// The thing to look inside of
+    pub container: Option<Box<TargetSpec>>,
+}
+
+impl TargetSpec {
+    /// Parses the arguments of a command, like `["2.sword", "in", "bag"]`
+    pub fn parse(args: &[String]) -> Option<Self> {
+        let split = args
+            .iter()
+            .skip(1)
+            .position(|arg| arg.eq_ignore_ascii_case("in"))
+            .map(|i| i + 1);
+
+        let (target, container) = match split {
+            Some(i) => (&args[..i], Some(Box::new(Self::parse(&args[i + 1..])?))),
+            None => (args, None),
+        };
+
+        let (first, rest) = target.split_first()?;
+        let (selector, first) = match first.split_once('.') {
+            Some((prefix, name)) if prefix.eq_ignore_ascii_case("all") => (Selector::All, name),
+            Some((prefix, name)) => match prefix.parse::<usize>() {
+                Ok(n) if n > 0 => (Selector::Nth(n), name),
+                _ => (Selector::Nth(1), first.as_str()),
+            },
+            None if first.eq_ignore_ascii_case("all") && rest.is_empty() => (Selector::All, ""),
+            None => (Selector::Nth(1), first.as_str()),
+        };
+
+        let keywords: Vec<String> = std::iter::once(first)
+            .chain(rest.iter().map(String::as_str))
+            .flat_map(str::split_whitespace)
+            .map(str::to_lowercase)
+            .collect();
+
+        if keywords.is_empty() && selector != Selector::All {
+            return None;
+        }
+
+        Some(Self {
+            selector,
+            keywords,
+            container,
+        })
+    }
+
+    /// Whether every keyword is the start of some word in `name`
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.keywords.iter().all(|keyword| {
+            name.split_whitespace()
+                .any(|word| word.starts_with(keyword.as_str()))
+        })
+    }
+
+    /// Picks the matching entities from `candidates`, in order
+    pub fn select<'a>(
+        &self,
+        candidates: impl IntoIterator<Item = (Entity, &'a str)>,
+    ) -> Vec<Entity> {
+        let mut matching = candidates
+            .into_iter()
+            .filter(|(_, name)| self.matches(name))
+            .map(|(entity, _)| entity);
+
+        match self.selector {
+            Selector::All => matching.collect(),
+            Selector::Nth(n) => n
+                .checked_sub(1)
+                .and_then(|i| matching.nth(i))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Finds the target among the contents of `scope`, usually the room the character is in.
+    /// A container is looked up in `scope` first, and the target is then looked for in the
+    /// container's own contents.
+    pub fn resolve(
+        &self,
+        scope: Entity,
+        contents_query: &Query<&RoomContents>,
+        name_query: &Query<&Name>,
+    ) -> Vec<Entity> {
+        let scope = match &self.container {
+            Some(container) => {
+                let containers = container.resolve(scope, contents_query, name_query);
+                let Some(&container) = containers.first() else {
+                    return Vec::new();
+                };
+                container
+            }
+            None => scope,
+        };
+
+        let Ok(contents) = contents_query.get(scope) else {
+            return Vec::new();
+        };
+
+        self.select(
+            contents
+                .iter()
+                .filter_map(|entity| Some((entity, name_query.get(entity).ok()?.as_str()))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn tokenize_splits_on_whitespace() {
+        assert_eq!(tokenize("  get   sword \t"), ["get", "sword"]);
+        assert!(tokenize("   ").is_empty());
+    }
+
+    #[test]
+    fn tokenize_keeps_quoted_text_together() {
+        assert_eq!(
+            tokenize("say 'hello there' bob"),
+            ["say", "hello there", "bob"]
+        );
+        assert_eq!(tokenize("tell \"big bob\" hi"), ["tell", "big bob", "hi"]);
+        assert_eq!(tokenize("look ''"), ["look", ""]);
+        assert_eq!(
+            tokenize("say 'unfinished quote"),
+            ["say", "unfinished quote"]
+        );
+    }
+
+    #[test]
+    fn tokenize_keeps_apostrophes_in_words() {
+        assert_eq!(tokenize("get bob's sword"), ["get", "bob's", "sword"]);
+        assert_eq!(tokenize("say 'bob's sword'"), ["say", "bob's sword"]);
+        assert_eq!(tokenize("say it's fine"), ["say", "it's", "fine"]);
+    }
+
+    #[test]
+    fn parses_selectors() {
+        let spec = TargetSpec::parse(&words("2.sword")).unwrap();
+        assert_eq!(spec.selector, Selector::Nth(2));
+        assert_eq!(spec.keywords, ["sword"]);
+
+        let spec = TargetSpec::parse(&words("all")).unwrap();
+        assert_eq!(spec.selector, Selector::All);
+        assert!(spec.keywords.is_empty());
+
+        let spec = TargetSpec::parse(&words("all.coin")).unwrap();
+        assert_eq!(spec.selector, Selector::All);
+        assert_eq!(spec.keywords, ["coin"]);
+
+        let spec = TargetSpec::parse(&words("long Sword")).unwrap();
+        assert_eq!(spec.selector, Selector::Nth(1));
+        assert_eq!(spec.keywords, ["long", "sword"]);
+    }
+
+    #[test]
+    fn parses_nested_containers() {
+        let spec = TargetSpec::parse(&words("sword in bag in chest")).unwrap();
+        assert_eq!(spec.keywords, ["sword"]);
+
+        let bag = spec.container.unwrap();
+        assert_eq!(bag.keywords, ["bag"]);
+
+        let chest = bag.container.unwrap();
+        assert_eq!(chest.keywords, ["chest"]);
+        assert!(chest.container.is_none());
+    }
+
+    #[test]
+    fn rejects_targets_without_keywords() {
+        assert_eq!(TargetSpec::parse(&words("2.")), None);
+        assert_eq!(TargetSpec::parse(&[]), None);
+        assert_eq!(TargetSpec::parse(&words("sword in")), None);
+    }
+
+    #[test]
+    fn selects_matching_entities() {
+        let candidates = [
+            (Entity::from_raw(1), "a rusty sword"),
+            (Entity::from_raw(2), "a leather bag"),
+            (Entity::from_raw(3), "a shiny sword"),
+        ];
+
+        let first = TargetSpec::parse(&words("sword")).unwrap();
+        assert_eq!(first.select(candidates), [Entity::from_raw(1)]);
+
+        let second = TargetSpec::parse(&words("2.sw")).unwrap();
+        assert_eq!(second.select(candidates), [Entity::from_raw(3)]);
+
+        let third = TargetSpec::parse(&words("3.sword")).unwrap();
+        assert!(third.select(candidates).is_empty());
+
+        let all = TargetSpec::parse(&words("all.sword")).unwrap();
+        assert_eq!(
+            all.select(candidates),
+            [Entity::from_raw(1), Entity::from_raw(3)]
+        );
+
+        let everything = TargetSpec::parse(&words("all")).unwrap();
+        assert_eq!(everything.select(candidates).len(), 3);
+    }
+}
@@ -0,0 +1,225 @@
+//! The commands characters can type while exploring
+//!
+//! Plugins register every command once with [`AppCommandRegistryEx::add_command`], together with
+//! an observer handling it. The first word of a line is looked up in the [`CommandRegistry`], and
+//! [`ExplorationCommandEvent`] is triggered on the matching command only.
+use std::cmp::Reverse;
+
+use bevy::{ecs::system::IntoObserverSystem, prelude::*};
+
+use crate::permissions::{Permission, Permissions};
+
+use super::ExplorationCommandEvent;
+
+/// Describes a command: what it's called, how it may be abbreviated and who can use it
+pub struct CommandDef {
+    pub name: &'static str,
+    /// Other words that run the command, like `n` for `north`
+    pub aliases: Vec<&'static str>,
+    /// How many letters of the name have to be typed at least. Defaults to the whole name.
+    pub min_prefix: usize,
+    /// Decides which command an abbreviation shared by several commands runs
+    pub priority: i32,
+    /// Characters without this permission are told the command doesn't exist
+    pub permission: Option<Permission>,
+    /// One line shown by `help`
+    pub help: &'static str,
+    requirements: Vec<fn(&EntityRef) -> bool>,
+}
+
+impl CommandDef {
+    pub fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            aliases: Vec::new(),
+            min_prefix: name.len(),
+            priority: 0,
+            permission: None,
+            help,
+            requirements: Vec::new(),
+        }
+    }
+
+    pub fn alias(mut self, alias: &'static str) -> Self {
+        self.aliases.push(alias);
+        self
+    }
+
+    pub fn min_prefix(mut self, min_prefix: usize) -> Self {
+        self.min_prefix = min_prefix;
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn permission(mut self, permission: Permission) -> Self {
+        self.permission = Some(permission);
+        self
+    }
+
+    /// Only let characters with a `T` component run the command
+    pub fn requires<T: Component>(mut self) -> Self {
+        self.requirements.push(has_component::<T>);
+        self
+    }
+
+    /// Whether `character` is allowed to know about the command
+    pub fn permitted(&self, permissions: &Permissions, character: Entity) -> bool {
+        self.permission
+            .is_none_or(|permission| permissions.has(character, permission))
+    }
+
+    /// Whether `character` is in a state to run the command
+    pub fn available(&self, character: &EntityRef) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| requirement(character))
+    }
+
+    fn is_called(&self, word: &str) -> bool {
+        self.name == word || self.aliases.iter().any(|alias| *alias == word)
+    }
+
+    fn is_abbreviated_by(&self, word: &str) -> bool {
+        word.len() >= self.min_prefix.max(1) && self.name.starts_with(word)
+    }
+}
+
+fn has_component<T: Component>(entity: &EntityRef) -> bool {
+    entity.contains::<T>()
+}
+
+/// Every registered command, with the entity its handler observes
+#[derive(Resource, Default)]
+pub struct CommandRegistry {
+    commands: Vec<(CommandDef, Entity)>,
+}
+
+impl CommandRegistry {
+    /// Finds the command `word` refers to among the ones `visible` accepts. A name or alias
+    /// beats an abbreviation, then the highest priority wins, then the first registered.
+    pub fn resolve(
+        &self,
+        word: &str,
+        visible: impl Fn(&CommandDef) -> bool,
+    ) -> Option<(&CommandDef, Entity)> {
+        let word = word.to_lowercase();
+        let best = |matches: fn(&CommandDef, &str) -> bool| {
+            self.commands
+                .iter()
+                .filter(|(def, _)| visible(def) && matches(def, &word))
+                .min_by_key(|(def, _)| Reverse(def.priority))
+                .map(|(def, handler)| (def, *handler))
+        };
+
+        best(CommandDef::is_called).or_else(|| best(CommandDef::is_abbreviated_by))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CommandDef> {
+        self.commands.iter().map(|(def, _)| def)
+    }
+}
+
+pub trait AppCommandRegistryEx {
+    /// Registers a command, with an observer that is triggered whenever it's used
+    fn add_command<M>(
+        &mut self,
+        def: CommandDef,
+        handler: impl IntoObserverSystem<ExplorationCommandEvent, (), M>,
+    ) -> &mut Self;
+}
+
+impl AppCommandRegistryEx for App {
+    fn add_command<M>(
+        &mut self,
+        def: CommandDef,
+        handler: impl IntoObserverSystem<ExplorationCommandEvent, (), M>,
+    ) -> &mut Self {
+        let world = self.world_mut();
+        let handler = world
+            .spawn(Name::new(format!("Command {}", def.name)))
+            .observe(handler)
+            .id();
+
+        world
+            .get_resource_or_init::<CommandRegistry>()
+            .commands
+            .push((def, handler));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(defs: impl IntoIterator<Item = CommandDef>) -> CommandRegistry {
+        CommandRegistry {
+            commands: defs
+                .into_iter()
+                .enumerate()
+                .map(|(i, def)| (def, Entity::from_raw(i as u32)))
+                .collect(),
+        }
+    }
+
+    fn resolve(registry: &CommandRegistry, word: &str) -> Option<&'static str> {
+        registry.resolve(word, |_| true).map(|(def, _)| def.name)
+    }
+
+    #[test]
+    fn names_and_aliases_beat_abbreviations() {
+        let registry = registry([
+            CommandDef::new("sayto", "").min_prefix(1).priority(10),
+            CommandDef::new("say", "").min_prefix(1),
+            CommandDef::new("news", "").min_prefix(1).priority(10),
+            CommandDef::new("north", "").alias("n"),
+        ]);
+
+        assert_eq!(resolve(&registry, "say"), Some("say"));
+        assert_eq!(resolve(&registry, "SAY"), Some("say"));
+        assert_eq!(resolve(&registry, "n"), Some("north"));
+        assert_eq!(resolve(&registry, "s"), Some("sayto"));
+    }
+
+    #[test]
+    fn priority_picks_between_abbreviations() {
+        let registry = registry([
+            CommandDef::new("get", "").min_prefix(1),
+            CommandDef::new("give", "").min_prefix(1).priority(5),
+            CommandDef::new("look", "").min_prefix(1),
+            CommandDef::new("lock", "").min_prefix(1),
+        ]);
+
+        assert_eq!(resolve(&registry, "g"), Some("give"));
+        assert_eq!(resolve(&registry, "ge"), Some("get"));
+        // Equal priorities go to the first registered
+        assert_eq!(resolve(&registry, "lo"), Some("look"));
+        assert_eq!(resolve(&registry, "loc"), Some("lock"));
+    }
+
+    #[test]
+    fn respects_min_prefix() {
+        let registry = registry([CommandDef::new("shutdown", "")]);
+
+        assert_eq!(resolve(&registry, "shut"), None);
+        assert_eq!(resolve(&registry, "shutdown"), Some("shutdown"));
+        assert_eq!(resolve(&registry, "shutdowns"), None);
+        assert_eq!(resolve(&registry, ""), None);
+    }
+
+    #[test]
+    fn skips_invisible_commands() {
+        let registry = registry([
+            CommandDef::new("goto", "").min_prefix(1).priority(10),
+            CommandDef::new("get", "").min_prefix(1),
+        ]);
+
+        let found = registry.resolve("g", |def| def.name != "goto");
+        assert_eq!(found.map(|(def, _)| def.name), Some("get"));
+        assert_eq!(found.map(|(_, handler)| handler), Some(Entity::from_raw(1)));
+    }
+}
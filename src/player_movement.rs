@@ -1,18 +1,49 @@
 use bevy::prelude::*;
 
 use crate::{
-    player_commands::ExplorationCommandEvent,
+    player_commands::{AppCommandRegistryEx, CommandDef, ExplorationCommandEvent},
+    telnet::{EventWriterTelnetEx, SendMessageAction},
     world::{
         exit::{Exit, InExit, OutExits},
         room::{InRoom, MoveRoomAction},
     },
 };
 
+/// Directions that can be walked by typing them, and their short forms
+const DIRECTIONS: [(&str, &str); 10] = [
+    ("north", "n"),
+    ("south", "s"),
+    ("east", "e"),
+    ("west", "w"),
+    ("up", "u"),
+    ("down", "d"),
+    ("northeast", "ne"),
+    ("northwest", "nw"),
+    ("southeast", "se"),
+    ("southwest", "sw"),
+];
+
 pub struct PlayerMovementPlugin;
 
 impl Plugin for PlayerMovementPlugin {
     fn build(&self, app: &mut App) {
-        app.add_observer(move_command);
+        for (direction, alias) in DIRECTIONS {
+            app.add_command(
+                CommandDef::new(direction, "Walk through the exit in that direction")
+                    .alias(alias)
+                    .min_prefix(1)
+                    .priority(20)
+                    .requires::<InRoom>(),
+                move_command,
+            );
+        }
+
+        // Exits aren't limited to the usual directions, like "enter cave"
+        app.add_command(
+            CommandDef::new("go", "Walk through an exit, like \"go enter cave\"")
+                .requires::<InRoom>(),
+            move_command,
+        );
     }
 }
 
@@ -23,42 +54,48 @@ fn move_command(
     out_exit_query: Query<&OutExits>,
     exit_query: Query<&Exit>,
     in_exit_query: Query<&InExit>,
+    mut sender: EventWriter<SendMessageAction>,
 ) -> Result {
-    static ALIASES: phf::Map<&'static str, &'static str> = phf::phf_map! {
-        "u" => "up",
-        "d" => "down",
-        "n" => "north",
-        "s" => "south",
-        "e" => "east",
-        "w" => "west",
+    let character = trigger.character;
+    let direction = match trigger.command.as_str() {
+        "go" => trigger.args.join(" "),
+        direction => direction.to_string(),
     };
 
-    let conn = trigger.target();
-    let room = room_query.get(conn)?.0;
+    if direction.is_empty() {
+        sender.println(character, "Go where?");
+        return Ok(());
+    }
+
+    let room = room_query.get(character)?.0;
+    let exit = out_exit_query.get(room).ok().and_then(|exits| {
+        exits.iter().find_map(|exit_ent| {
+            let exit = exit_query.get(exit_ent).ok()?;
+            exit.direction
+                .eq_ignore_ascii_case(&direction)
+                .then_some((exit_ent, exit))
+        })
+    });
 
-    let Ok(exits) = out_exit_query.get(room) else {
+    let Some((exit_ent, exit)) = exit else {
+        sender.println(character, "You can't go that way.");
         return Ok(());
     };
 
-    for exit_ent in exits.iter() {
-        if let Ok(exit) = exit_query.get(exit_ent) {
-            if exit.direction == trigger.line
-                || ALIASES
-                    .get(&trigger.line)
-                    .is_some_and(|x| &exit.direction == x)
-            {
-                let target_room_ent = in_exit_query.get(exit_ent)?.0;
-                commands.trigger_targets(
-                    MoveRoomAction {
-                        old_room: Some(room),
-                        new_room: target_room_ent,
-                        direction: Some(exit.direction.clone()),
-                    },
-                    conn,
-                );
-            }
-        }
-    }
+    // Exits from area files have no target until the room they lead to exists
+    let Ok(target) = in_exit_query.get(exit_ent) else {
+        sender.println(character, "That way leads nowhere.");
+        return Ok(());
+    };
+
+    commands.trigger_targets(
+        MoveRoomAction {
+            old_room: Some(room),
+            new_room: target.0,
+            direction: Some(exit.direction.clone()),
+        },
+        character,
+    );
 
     Ok(())
 }
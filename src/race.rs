@@ -15,6 +15,7 @@ impl Plugin for RacePlugin {
 
 #[derive(sqlx::FromRow, Clone, Reflect)]
 pub struct RaceDef {
+    #[sqlx(try_from = "i64")]
     pub id: u64,
     pub name: String,
 }
@@ -28,6 +29,10 @@ pub static PLACEHOLDER_RACEDEF: LazyLock<RaceDef> = LazyLock::new(|| RaceDef {
 pub struct Races(Vec<RaceDef>);
 
 impl Races {
+    pub fn iter(&self) -> impl Iterator<Item = &RaceDef> {
+        self.0.iter()
+    }
+
     pub fn get_race(&self, id: u64) -> &RaceDef {
         self.0
             .iter()
@@ -0,0 +1,161 @@
+//! Tracks which connection controls which character, and keeps characters around for a while
+//! after their connection has been lost
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::{
+    persistence::SaveCharacterAction,
+    player_commands::Exploring,
+    telnet::ConnectionClosed,
+    world::room::{InRoom, RoomBroadcastAction, ShowRoomDescriptionAction},
+};
+
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Character>()
+            .register_type::<ControlledBy>()
+            .register_type::<Controlling>()
+            .register_type::<LinkDead>()
+            .init_resource::<SessionSettings>()
+            .add_observer(on_connection_closed)
+            .add_observer(on_reconnect_action)
+            .add_systems(Update, link_dead_timeout);
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct SessionSettings {
+    /// How long a character stays in the world after losing its connection
+    pub link_dead_timeout: Duration,
+    /// Characters of the same account that can be in the world at once
+    pub max_characters_per_account: usize,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            link_dead_timeout: Duration::from_secs(180),
+            max_characters_per_account: 1,
+        }
+    }
+}
+
+/// A character from the `characters` table that is present in the world
+#[derive(Clone, Copy, Debug, Reflect, Component)]
+pub struct Character {
+    pub id: u64,
+    pub account: u64,
+}
+
+/// Component placed on characters pointing to the connection controlling them
+#[derive(Debug, Reflect, Component)]
+#[relationship(relationship_target = Controlling)]
+pub struct ControlledBy(pub Entity);
+
+/// Component placed on connections pointing to the character they control
+#[derive(Debug, Reflect, Component)]
+#[relationship_target(relationship = ControlledBy)]
+pub struct Controlling(Entity);
+
+impl Controlling {
+    pub fn character(&self) -> Entity {
+        self.0
+    }
+}
+
+/// A character whose connection was lost. It is removed from the world once the timer runs out,
+/// unless a new connection takes control of it first.
+#[derive(Debug, Reflect, Component)]
+pub struct LinkDead(Timer);
+
+/// Attach a new connection to a link-dead character
+/// Event target is the character
+#[derive(Clone, Debug, Reflect, Event)]
+pub struct ReconnectAction {
+    pub connection: Entity,
+}
+
+fn on_connection_closed(
+    trigger: Trigger<ConnectionClosed>,
+    mut commands: Commands,
+    settings: Res<SessionSettings>,
+    controlling_query: Query<&Controlling>,
+    character_query: Query<(&Name, Option<&InRoom>)>,
+) {
+    let Ok(controlling) = controlling_query.get(trigger.target()) else {
+        return;
+    };
+    let character = controlling.character();
+
+    commands.entity(character).insert(LinkDead(Timer::new(
+        settings.link_dead_timeout,
+        TimerMode::Once,
+    )));
+
+    if let Ok((name, Some(room))) = character_query.get(character) {
+        commands.trigger_targets(
+            RoomBroadcastAction {
+                message: format!("{name} has lost their link.\r\n"),
+            },
+            room.0,
+        );
+    }
+}
+
+fn on_reconnect_action(
+    trigger: Trigger<ReconnectAction>,
+    mut commands: Commands,
+    character_query: Query<(&Name, Option<&InRoom>)>,
+) {
+    let character = trigger.target();
+
+    let room = if let Ok((name, Some(room))) = character_query.get(character) {
+        commands.trigger_targets(
+            RoomBroadcastAction {
+                message: format!("{name} has reconnected.\r\n"),
+            },
+            room.0,
+        );
+        Some(room.0)
+    } else {
+        None
+    };
+
+    // The character may have been in a menu when the link was lost, the new connection starts
+    // out exploring
+    commands
+        .entity(character)
+        .remove::<LinkDead>()
+        .insert((ControlledBy(trigger.connection), Exploring));
+
+    if let Some(room) = room {
+        commands.trigger_targets(ShowRoomDescriptionAction { room }, character);
+    }
+}
+
+fn link_dead_timeout(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut LinkDead, Option<&Name>, Option<&InRoom>)>,
+) {
+    for (character, mut link_dead, name, room) in &mut query {
+        if !link_dead.0.tick(time.delta()).finished() {
+            continue;
+        }
+
+        if let (Some(name), Some(room)) = (name, room) {
+            commands.trigger_targets(
+                RoomBroadcastAction {
+                    message: format!("{name} fades out of existence.\r\n"),
+                },
+                room.0,
+            );
+        }
+
+        commands.trigger_targets(SaveCharacterAction, character);
+        commands.entity(character).despawn();
+    }
+}
@@ -1,34 +1,151 @@
+use std::{
+    collections::VecDeque, fmt::Display, future::Future, net::SocketAddr, path::PathBuf,
+    time::Duration,
+};
+
 use async_channel::{Receiver, Sender, TryRecvError};
+use async_io::Timer;
 use async_net::{TcpListener, TcpStream};
 use bevy::{
-    asset::{AsyncReadExt, AsyncWriteExt},
+    asset::{AsyncRead, AsyncReadExt, AsyncWriteExt},
     prelude::*,
-    tasks::{IoTaskPool, Task, futures_lite::StreamExt},
+    tasks::{
+        IoTaskPool, Task,
+        futures_lite::{FutureExt, StreamExt},
+    },
 };
+use futures_rustls::server::TlsStream;
 use libmudtelnet::{Parser as TelnetParser, telnet::op_option};
 use libmudtelnet::{
     bytes::{Bytes, BytesMut},
     compatibility::CompatibilityTable,
-    events::{TelnetEvents, TelnetNegotiation},
+    events::{TelnetEvents, TelnetNegotiation, TelnetSubnegotiation},
     telnet::op_command,
 };
 
-pub struct TelnetPlugin;
+use line_buffer::LineBuffer;
+use mccp::{Deflater, Inflater};
+use websocket::WebSocketMode;
+
+use crate::session::ControlledBy;
+
+pub use gmcp::{Gmcp, GmcpCoreHello, GmcpCoreSupports, GmcpReceived};
+pub use mccp::Mccp;
+pub use mtts::{AnsiColor, ClientCapabilities, Mtts};
+pub use naws::TerminalSize;
+
+mod gmcp;
+mod line_buffer;
+mod mccp;
+mod mtts;
+mod naws;
+mod tls;
+mod websocket;
+
+pub struct TelnetPlugin {
+    /// Addresses to accept connections on
+    pub listeners: Vec<Listener>,
+}
 
 impl Plugin for TelnetPlugin {
     fn build(&self, app: &mut App) {
+        app.insert_resource(Listeners(self.listeners.clone()));
+        app.add_plugins((
+            naws::NawsPlugin,
+            mtts::MttsPlugin,
+            gmcp::GmcpPlugin,
+            mccp::MccpPlugin,
+        ));
+        app.register_type::<ConnectionStats>();
+        app.register_type::<Secure>();
+        app.init_resource::<InputSettings>();
         app.add_systems(Startup, startup);
-        app.add_systems(PreUpdate, (connection_handler, data_handler));
-        app.add_systems(PostUpdate, data_sender);
+        app.add_systems(
+            PreUpdate,
+            (connection_handler, (data_handler, input_dispatcher).chain()),
+        );
+        app.add_systems(PostUpdate, (data_sender, disconnect_handler).chain());
         app.add_event::<NewConnection>();
+        app.add_event::<ConnectionClosed>();
+        app.add_event::<DisconnectAction>();
         app.add_event::<MessageReceived>();
         app.add_event::<SendMessageAction>();
     }
 }
 
+/// Number of raw data chunks that can be buffered per connection before the reader waits
+const RAW_DATA_CHANNEL_CAPACITY: usize = 16;
+
+/// How long a client gets to finish a handshake before the connection is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Limits on how much input is processed per connection
+#[derive(Resource, Clone, Debug)]
+pub struct InputSettings {
+    /// Maximum number of lines dispatched per connection per tick
+    pub lines_per_tick: usize,
+    /// Maximum number of lines waiting to be dispatched per connection. Further input is
+    /// dropped, and reading from the socket is paused until the queue has drained.
+    pub max_queued_lines: usize,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            lines_per_tick: 4,
+            max_queued_lines: 64,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub address: String,
+    pub kind: ListenerKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenerKind {
+    /// Raw telnet over TCP
+    Telnet,
+    /// WebSocket, either as plain text lines or with telnet data in binary frames
+    WebSocket,
+    /// Telnet over TLS, using a PEM encoded certificate chain and private key
+    Tls { certificate: PathBuf, key: PathBuf },
+}
+
+#[derive(Resource, Clone, Debug)]
+struct Listeners(Vec<Listener>);
+
+/// A connection that was accepted by one of the listeners
+enum IncomingConnection {
+    Telnet(TcpStream),
+    WebSocket(async_tungstenite::WebSocketStream<TcpStream>, WebSocketMode),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
 #[derive(Resource)]
 struct Channel {
-    receiver: Receiver<TcpStream>,
+    receiver: Receiver<(IncomingConnection, SocketAddr)>,
+}
+
+/// Where a connection's event handler task writes outgoing data to
+trait TransportWriter: Send + 'static {
+    fn send(&mut self, data: &[u8]) -> impl Future<Output = std::io::Result<()>> + Send;
+
+    /// Flushes pending output and closes the connection
+    fn finish(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+impl TransportWriter for TcpStream {
+    async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.write_all(data).await
+    }
+
+    async fn finish(&mut self) {
+        let _ = self.flush().await;
+        let _ = self.shutdown(std::net::Shutdown::Both);
+    }
 }
 
 #[derive(Component)]
@@ -36,9 +153,14 @@ pub struct Connection {
     _reader_task: Task<()>,
     _event_handler: Task<()>,
     data_receiver: Receiver<Bytes>,
-    pub telnet_event_sender: Sender<TelnetEvents>,
+    telnet_event_sender: Sender<EventHandlerMessage>,
     telnet_event_receiver: Receiver<TelnetEvent>,
     pub parser: TelnetParser,
+    line_buffer: LineBuffer,
+    input_queue: VecDeque<Bytes>,
+    /// Decompresses input once the client started MCCP3
+    inflater: Option<Inflater>,
+    peer_addr: SocketAddr,
 }
 
 impl std::fmt::Debug for Connection {
@@ -49,10 +171,83 @@ impl std::fmt::Debug for Connection {
             .field("data_receiver", &self.data_receiver)
             .field("telnet_event_sender", &self.telnet_event_sender)
             .field("telnet_event_receiver", &self.telnet_event_receiver)
+            .field("line_buffer", &self.line_buffer)
+            .field("input_queue", &self.input_queue)
+            .field("inflater", &self.inflater.is_some())
+            .field("peer_addr", &self.peer_addr)
             .finish()
     }
 }
 
+impl Connection {
+    /// Address of the remote end of the connection
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Runs raw data from the socket through MCCP3 decompression and the telnet parser. Fails
+    /// if the compressed stream is corrupt, after which nothing it sends can be trusted.
+    fn receive(&mut self, data: &[u8]) -> Result<Vec<TelnetEvents>, flate2::DecompressError> {
+        let mut events = Vec::new();
+        let mut input = data.to_vec();
+
+        while !input.is_empty() {
+            let data = match self.inflater {
+                None => std::mem::take(&mut input),
+                Some(ref mut inflater) => match inflater.inflate(&input)? {
+                    (data, None) => {
+                        input.clear();
+                        data
+                    }
+                    (data, Some(rest)) => {
+                        // The client ended compression, the rest is uncompressed
+                        self.inflater = None;
+                        input = rest;
+                        data
+                    }
+                },
+            };
+
+            let mut mccp3_started = false;
+            for event in self.parser.receive(&data) {
+                match event {
+                    TelnetEvents::Subnegotiation(TelnetSubnegotiation { option, .. })
+                        if option == op_option::MCCP3 =>
+                    {
+                        mccp3_started = true;
+                        events.push(event);
+                    }
+                    TelnetEvents::DecompressImmediate(rest) => {
+                        // The parser stops at the start of a compressed stream and hands back
+                        // everything after it
+                        if mccp3_started {
+                            self.inflater = Some(Inflater::new());
+                        }
+                        let mut remaining = rest.to_vec();
+                        remaining.append(&mut input);
+                        input = remaining;
+                    }
+                    event => events.push(event),
+                }
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+/// Input statistics of a connection
+#[derive(Component, Clone, Debug, Default, Reflect)]
+pub struct ConnectionStats {
+    /// Lines waiting to be dispatched
+    pub queued_lines: usize,
+    /// Total lines received
+    pub received_lines: u64,
+    /// Lines dropped because the input queue was full
+    pub dropped_lines: u64,
+}
+
+/// A single line of input received from a connection, without the line terminator
 #[derive(Event, Clone)]
 pub struct MessageReceived {
     pub connection: Entity,
@@ -61,16 +256,14 @@ pub struct MessageReceived {
 
 impl MessageReceived {
     pub fn to_text(&self) -> String {
-        let mut ret = String::from_utf8_lossy(&self.data).into_owned();
-        if ret.ends_with("\r\n") {
-            ret.truncate(ret.len().saturating_sub(2));
-        } else if ret.ends_with("\r") || ret.ends_with("\n") {
-            ret.truncate(ret.len().saturating_sub(1));
-        }
-        ret
+        String::from_utf8_lossy(&self.data).into_owned()
     }
 }
 
+/// Send data to a connection
+///
+/// `connection` can also be a character, in which case the data is sent to the connection
+/// controlling it, if any.
 #[derive(Event, Clone)]
 pub struct SendMessageAction {
     pub connection: Entity,
@@ -104,6 +297,32 @@ pub trait EventWriterTelnetEx {
         );
     }
 
+    fn negotiate(&mut self, conn: Entity, command: u8, option: u8) {
+        self.send_message(
+            conn,
+            TelnetEvents::Negotiation(TelnetNegotiation { command, option }),
+        );
+    }
+
+    fn subnegotiate(&mut self, conn: Entity, option: u8, data: &[u8]) {
+        self.send_message(
+            conn,
+            TelnetEvents::Subnegotiation(TelnetSubnegotiation {
+                option,
+                buffer: Bytes::copy_from_slice(data),
+            }),
+        );
+    }
+
+    /// Sends a GMCP message, if the client has GMCP enabled
+    fn send_gmcp(&mut self, conn: Entity, package: &str, data: &serde_json::Value) {
+        self.subnegotiate(
+            conn,
+            op_option::GMCP,
+            format!("{package} {data}").as_bytes(),
+        );
+    }
+
     fn echo(&mut self, conn: Entity, echo: bool) {
         let command = if echo {
             op_command::WONT
@@ -111,13 +330,7 @@ pub trait EventWriterTelnetEx {
             op_command::WILL
         };
 
-        self.send_message(
-            conn,
-            TelnetEvents::Negotiation(TelnetNegotiation {
-                command,
-                option: op_option::ECHO,
-            }),
-        );
+        self.negotiate(conn, command, op_option::ECHO);
     }
 }
 
@@ -132,6 +345,15 @@ impl<'w> EventWriterTelnetEx for EventWriter<'w, SendMessageAction> {
 
 enum TelnetEvent {
     MessageReceived(Bytes),
+    Negotiation(TelnetNegotiation),
+    Subnegotiation(TelnetSubnegotiation),
+}
+
+/// Messages sent to a connection's event handler task
+enum EventHandlerMessage {
+    Telnet(TelnetEvents),
+    /// Flush pending output and close the connection
+    Close,
 }
 
 #[derive(Event)]
@@ -139,44 +361,156 @@ pub struct NewConnection {
     pub entity: Entity,
 }
 
+/// Fires when a connection has been closed, right before its entity is despawned
+/// Also triggered with the connection as the event target
+#[derive(Event, Clone)]
+pub struct ConnectionClosed {
+    pub entity: Entity,
+}
+
+/// Marker for connections that are encrypted
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+pub struct Secure;
+
+/// Fires when the client sent a telnet option negotiation
+/// Event target is the connection
+#[derive(Event, Clone, Debug)]
+pub struct NegotiationReceived {
+    pub command: u8,
+    pub option: u8,
+}
+
+/// Fires when the client sent a telnet subnegotiation
+/// Event target is the connection
+#[derive(Event, Clone, Debug)]
+pub struct SubnegotiationReceived {
+    pub option: u8,
+    pub data: Bytes,
+}
+
+/// Close a connection after sending all data that is already queued for it
+#[derive(Event, Clone)]
+pub struct DisconnectAction {
+    pub connection: Entity,
+}
+
+/// Runs a handshake, failing if the client doesn't finish it within [`HANDSHAKE_TIMEOUT`]
+async fn handshake<T, E: Display>(
+    handshake: impl Future<Output = Result<T, E>>,
+) -> Result<T, String> {
+    let timeout = async {
+        Timer::after(HANDSHAKE_TIMEOUT).await;
+        Err("Timed out".to_string())
+    };
+
+    async { handshake.await.map_err(|err| err.to_string()) }
+        .or(timeout)
+        .await
+}
+
 /// Listens to incoming connections and sends the stream to `sender`
-async fn connection_listener(sender: Sender<TcpStream>) {
-    let listener = TcpListener::bind("0.0.0.0:2222")
+async fn connection_listener(listener: Listener, sender: Sender<(IncomingConnection, SocketAddr)>) {
+    let acceptor = match &listener.kind {
+        ListenerKind::Tls { certificate, key } => match tls::acceptor(certificate, key) {
+            Ok(acceptor) => Some(acceptor),
+            Err(err) => {
+                error!("Could not set up TLS on {}: {}", listener.address, err);
+                return;
+            }
+        },
+        _ => None,
+    };
+
+    let socket = TcpListener::bind(&listener.address)
         .await
         .expect("Could not open socket");
-    let mut incoming = listener.incoming();
+    info!(
+        "Listening for {:?} connections on {}",
+        listener.kind, listener.address
+    );
+    let mut incoming = socket.incoming();
 
     while let Some(conn) = incoming.next().await {
-        if let Ok(stream) = conn {
-            sender.send(stream).await.expect("Channel closed");
+        let Ok(stream) = conn else {
+            continue;
+        };
+        let Ok(peer_addr) = stream.peer_addr() else {
+            continue;
+        };
+
+        match &listener.kind {
+            ListenerKind::Telnet => {
+                sender
+                    .send((IncomingConnection::Telnet(stream), peer_addr))
+                    .await
+                    .expect("Channel closed");
+            }
+            ListenerKind::WebSocket => {
+                // Handshake in a separate task, so a slow client can't hold up the listener
+                let sender = sender.clone();
+                IoTaskPool::get()
+                    .spawn(async move {
+                        match handshake(websocket::accept(stream)).await {
+                            Ok((websocket, mode)) => {
+                                let _ = sender
+                                    .send((
+                                        IncomingConnection::WebSocket(websocket, mode),
+                                        peer_addr,
+                                    ))
+                                    .await;
+                            }
+                            Err(err) => debug!("WebSocket handshake failed: {}", err),
+                        }
+                    })
+                    .detach();
+            }
+            ListenerKind::Tls { .. } => {
+                let sender = sender.clone();
+                let acceptor = acceptor.clone().expect("TLS acceptor missing");
+                IoTaskPool::get()
+                    .spawn(async move {
+                        match handshake(acceptor.accept(stream)).await {
+                            Ok(stream) => {
+                                let _ = sender
+                                    .send((IncomingConnection::Tls(Box::new(stream)), peer_addr))
+                                    .await;
+                            }
+                            Err(err) => debug!("TLS handshake failed: {}", err),
+                        }
+                    })
+                    .detach();
+            }
         }
     }
 }
 
-fn startup(mut commands: Commands) {
+fn startup(mut commands: Commands, listeners: Res<Listeners>) {
     let (tx, rx) = async_channel::unbounded();
 
-    IoTaskPool::get().spawn(connection_listener(tx)).detach();
+    for listener in &listeners.0 {
+        IoTaskPool::get()
+            .spawn(connection_listener(listener.clone(), tx.clone()))
+            .detach();
+    }
 
     commands.insert_resource(Channel { receiver: rx });
 }
 
 /// Reads data from `stream` and sends the read bytes to `sender`
-async fn connection_reader(mut stream: TcpStream, sender: Sender<Bytes>) {
+async fn connection_reader(mut stream: impl AsyncRead + Unpin, sender: Sender<Bytes>) {
     let mut buf = [0; 1024];
 
     loop {
-        if let Ok(n) = stream.read(&mut buf).await {
-            if n == 0 {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => {
                 // Connection closed
                 sender.close();
                 return;
-            } else {
+            }
+            Ok(n) => {
                 let data = BytesMut::from(&buf[0..n]);
                 if sender.send(data.freeze()).await.is_err() {
-                    // Channel closed?
-                    let _ = stream.write(b"Internal Error: TCP Channel closed.").await;
-                    let _ = stream.close().await;
+                    // Channel closed, the connection is gone. The event handler closes the socket.
                     return;
                 }
             }
@@ -185,29 +519,82 @@ async fn connection_reader(mut stream: TcpStream, sender: Sender<Bytes>) {
 }
 
 async fn telnet_event_handler(
-    event_rx: Receiver<TelnetEvents>,
-    mut socket: TcpStream,
+    event_rx: Receiver<EventHandlerMessage>,
+    mut socket: impl TransportWriter,
     event_tx: Sender<TelnetEvent>,
 ) {
-    while let Ok(event) = event_rx.recv().await {
-        match event {
-            TelnetEvents::IAC(_) => debug!("IAC"),
-            TelnetEvents::Negotiation(negotioation) => debug!("Negotiation: {:?}", negotioation),
-            TelnetEvents::Subnegotiation(_) => debug!("Subnegotiation"),
-            TelnetEvents::DataReceive(data) => {
-                trace!("Data received: {:?}", data);
-                event_tx
-                    .send(TelnetEvent::MessageReceived(data))
-                    .await
-                    .expect("todo");
-            }
-            TelnetEvents::DataSend(data) => {
-                trace!("Sending data");
-                socket.write_all(&data).await.expect("todo");
+    // Compresses all output once the client agreed to MCCP2
+    let mut deflater: Option<Deflater> = None;
+
+    while let Ok(message) = event_rx.recv().await {
+        let event = match message {
+            EventHandlerMessage::Telnet(event) => event,
+            EventHandlerMessage::Close => break,
+        };
+
+        // Returning drops both channels, which the ECS side sees as the connection closing
+        if let Err(err) = handle_telnet_event(event, &mut socket, &mut deflater, &event_tx).await {
+            debug!("Closing connection: {err}");
+            break;
+        }
+    }
+
+    if let Some(deflater) = deflater.take()
+        && let Ok(data) = deflater.finish()
+    {
+        let _ = socket.send(&data).await;
+    }
+    socket.finish().await;
+}
+
+async fn handle_telnet_event(
+    event: TelnetEvents,
+    socket: &mut impl TransportWriter,
+    deflater: &mut Option<Deflater>,
+    event_tx: &Sender<TelnetEvent>,
+) -> Result {
+    match event {
+        TelnetEvents::IAC(_) => debug!("IAC"),
+        TelnetEvents::Negotiation(negotiation) => {
+            debug!("Negotiation: {:?}", negotiation);
+
+            if negotiation.option == op_option::MCCP2 {
+                if negotiation.command == op_command::DO && deflater.is_none() {
+                    // Everything after this subnegotiation is compressed
+                    let start = subnegotiation_bytes(op_option::MCCP2, &[]);
+                    socket.send(&start).await?;
+                    *deflater = Some(Deflater::new());
+                } else if negotiation.command == op_command::DONT
+                    && let Some(deflater) = deflater.take()
+                {
+                    socket.send(&deflater.finish()?).await?;
+                }
             }
-            TelnetEvents::DecompressImmediate(_) => debug!("Decompress"),
+
+            event_tx.send(TelnetEvent::Negotiation(negotiation)).await?;
+        }
+        TelnetEvents::Subnegotiation(subnegotiation) => {
+            debug!("Subnegotiation");
+            event_tx
+                .send(TelnetEvent::Subnegotiation(subnegotiation))
+                .await?;
         }
+        TelnetEvents::DataReceive(data) => {
+            trace!("Data received: {:?}", data);
+            event_tx.send(TelnetEvent::MessageReceived(data)).await?;
+        }
+        TelnetEvents::DataSend(data) => {
+            trace!("Sending data");
+            let data = match deflater {
+                Some(deflater) => deflater.compress(&data)?,
+                None => data,
+            };
+            socket.send(&data).await?;
+        }
+        TelnetEvents::DecompressImmediate(_) => debug!("Decompress"),
     }
+
+    Ok(())
 }
 
 fn connection_handler(
@@ -215,40 +602,92 @@ fn connection_handler(
     channel: Res<Channel>,
     mut new_connection_event: EventWriter<NewConnection>,
 ) {
-    while let Ok(stream) = channel.receiver.try_recv() {
-        let (tcp_sender, tcp_receiver) = async_channel::unbounded(); // Raw received data
+    while let Ok((incoming, peer_addr)) = channel.receiver.try_recv() {
+        // Raw received data
+        let (tcp_sender, tcp_receiver) = async_channel::bounded(RAW_DATA_CHANNEL_CAPACITY);
 
         // Outgoing telnet events
         let (telnet_out_sender, telnet_out_receiver) = async_channel::unbounded();
         // Incoming telnet events
         let (telnet_in_sender, telnet_in_receiver) = async_channel::unbounded();
 
-        let reader_task = {
-            let stream = stream.clone();
-            IoTaskPool::get().spawn(async move { connection_reader(stream, tcp_sender).await })
-        };
+        let secure = matches!(incoming, IncomingConnection::Tls(_));
+
+        let (reader_task, event_handler) = match incoming {
+            IncomingConnection::Telnet(stream) => {
+                let reader_task = {
+                    let stream = stream.clone();
+                    IoTaskPool::get()
+                        .spawn(async move { connection_reader(stream, tcp_sender).await })
+                };
+
+                let event_handler = IoTaskPool::get().spawn(async move {
+                    telnet_event_handler(telnet_out_receiver, stream, telnet_in_sender).await
+                });
+
+                (reader_task, event_handler)
+            }
+            IncomingConnection::WebSocket(websocket, mode) => {
+                let (stream, writer) = websocket::split(websocket, mode);
+
+                let reader_task = IoTaskPool::get().spawn(async move {
+                    websocket::websocket_reader(stream, mode, tcp_sender).await
+                });
+
+                let event_handler = IoTaskPool::get().spawn(async move {
+                    telnet_event_handler(telnet_out_receiver, writer, telnet_in_sender).await
+                });
+
+                (reader_task, event_handler)
+            }
+            IncomingConnection::Tls(stream) => {
+                let (reader, writer) = tls::split(*stream);
+
+                let reader_task = IoTaskPool::get()
+                    .spawn(async move { connection_reader(reader, tcp_sender).await });
+
+                let event_handler = IoTaskPool::get().spawn(async move {
+                    telnet_event_handler(telnet_out_receiver, writer, telnet_in_sender).await
+                });
 
-        let event_handler = {
-            let stream = stream.clone();
-            IoTaskPool::get().spawn(async move {
-                telnet_event_handler(telnet_out_receiver, stream, telnet_in_sender).await
-            })
+                (reader_task, event_handler)
+            }
         };
 
         let parser = TelnetParser::with_support({
             let mut table = CompatibilityTable::new();
             table.support(op_option::ECHO);
+            table.support_remote(op_option::NAWS);
+            table.support_remote(op_option::TTYPE);
+            table.support_local(op_option::GMCP);
+            table.support_local(op_option::MCCP2);
+            table.support_local(op_option::MCCP3);
+            table.support_local(op_option::MSSP);
             table
         });
 
-        let entity = commands.spawn(Connection {
-            _reader_task: reader_task,
-            _event_handler: event_handler,
-            data_receiver: tcp_receiver,
-            telnet_event_sender: telnet_out_sender,
-            telnet_event_receiver: telnet_in_receiver,
-            parser,
-        });
+        let mut entity = commands.spawn((
+            Connection {
+                _reader_task: reader_task,
+                _event_handler: event_handler,
+                data_receiver: tcp_receiver,
+                telnet_event_sender: telnet_out_sender,
+                telnet_event_receiver: telnet_in_receiver,
+                parser,
+                line_buffer: LineBuffer::default(),
+                input_queue: VecDeque::new(),
+                inflater: None,
+                peer_addr,
+            },
+            ConnectionStats::default(),
+            TerminalSize::default(),
+            ClientCapabilities::default(),
+            Gmcp::default(),
+            Mccp::default(),
+        ));
+        if secure {
+            entity.insert(Secure);
+        }
 
         new_connection_event.write(NewConnection {
             entity: entity.id(),
@@ -257,71 +696,183 @@ fn connection_handler(
 }
 
 /// Receives raw socket data, parses it, and sends possible send events back out
-/// Also retrieves incoming telnet events and emits corresponding events
+/// Also retrieves incoming lines and places them in the connection's input queue
 fn data_handler(
     mut commands: Commands,
-    mut query: Query<(Entity, &mut Connection)>,
-    mut message_event: EventWriter<MessageReceived>,
+    mut query: Query<(Entity, &mut Connection, &mut ConnectionStats)>,
+    settings: Res<InputSettings>,
+    mut closed_event: EventWriter<ConnectionClosed>,
 ) {
-    for (entity, mut connection) in &mut query {
-        match connection.data_receiver.try_recv() {
-            Ok(data) => {
-                let events = connection.parser.receive(&data);
-                for event in events {
-                    if connection.telnet_event_sender.try_send(event).is_err() {
-                        error!("Could not send telnet event");
-                        todo!()
+    for (entity, mut connection, mut stats) in &mut query {
+        let connection = &mut *connection;
+        let mut closed = false;
+
+        // Stop reading once the input queue is full. The raw data channel is bounded, so this
+        // throttles the client until it has caught up.
+        while connection.input_queue.len() < settings.max_queued_lines {
+            match connection.data_receiver.try_recv() {
+                Ok(data) => {
+                    let events = match connection.receive(&data) {
+                        Ok(events) => events,
+                        Err(err) => {
+                            warn!("Closing connection, failed to decompress input: {err}");
+                            let _ = connection
+                                .telnet_event_sender
+                                .try_send(EventHandlerMessage::Close);
+                            closed = true;
+                            break;
+                        }
+                    };
+                    let sent = events.into_iter().all(|event| {
+                        connection
+                            .telnet_event_sender
+                            .try_send(EventHandlerMessage::Telnet(event))
+                            .is_ok()
+                    });
+                    if !sent {
+                        // The event handler task ended, so the socket is gone
+                        closed = true;
+                        break;
                     }
                 }
-            }
-            Err(TryRecvError::Closed) => {
-                // Connection closed
-                if let Ok(mut ent) = commands.get_entity(entity) {
-                    ent.despawn();
+                Err(TryRecvError::Closed) => {
+                    closed = true;
+                    break;
                 }
-            }
-            Err(TryRecvError::Empty) => {
-                // No data
+                Err(TryRecvError::Empty) => break,
             }
         }
 
-        match connection.telnet_event_receiver.try_recv() {
-            Ok(TelnetEvent::MessageReceived(data)) => {
-                let event = MessageReceived {
-                    connection: entity,
-                    data,
-                };
-                message_event.write(event.clone());
-                commands.trigger_targets(event, entity);
-            }
-            Err(TryRecvError::Closed) => {
-                // Connection closed
-                if let Ok(mut ent) = commands.get_entity(entity) {
-                    ent.despawn();
+        loop {
+            match connection.telnet_event_receiver.try_recv() {
+                Ok(TelnetEvent::MessageReceived(data)) => {
+                    for line in connection.line_buffer.push(&data) {
+                        stats.received_lines += 1;
+                        if connection.input_queue.len() >= settings.max_queued_lines {
+                            stats.dropped_lines += 1;
+                            continue;
+                        }
+                        connection.input_queue.push_back(line);
+                    }
                 }
+                Ok(TelnetEvent::Negotiation(TelnetNegotiation { command, option })) => {
+                    commands.trigger_targets(NegotiationReceived { command, option }, entity);
+                }
+                Ok(TelnetEvent::Subnegotiation(TelnetSubnegotiation { option, buffer })) => {
+                    let data = TelnetParser::unescape_iac(buffer);
+                    commands.trigger_targets(SubnegotiationReceived { option, data }, entity);
+                }
+                Err(TryRecvError::Closed) => {
+                    closed = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        stats.queued_lines = connection.input_queue.len();
+
+        if closed {
+            let event = ConnectionClosed { entity };
+            closed_event.write(event.clone());
+            commands.trigger_targets(event, entity);
+            if let Ok(mut ent) = commands.get_entity(entity) {
+                ent.despawn();
             }
-            Err(TryRecvError::Empty) => {}
         }
     }
 }
 
-fn data_sender(mut events: EventReader<SendMessageAction>, mut query: Query<&mut Connection>) {
+/// Emits queued input lines as `MessageReceived` events
+///
+/// Lines are taken from the connections in rounds, one line per connection per round, so a
+/// single client sending a lot of input can't starve the others.
+fn input_dispatcher(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Connection, &mut ConnectionStats)>,
+    settings: Res<InputSettings>,
+    mut message_event: EventWriter<MessageReceived>,
+) {
+    for _ in 0..settings.lines_per_tick {
+        let mut dispatched = false;
+
+        for (entity, mut connection, mut stats) in &mut query {
+            let Some(line) = connection.input_queue.pop_front() else {
+                continue;
+            };
+            dispatched = true;
+            stats.queued_lines = connection.input_queue.len();
+
+            let event = MessageReceived {
+                connection: entity,
+                data: line,
+            };
+            message_event.write(event.clone());
+            commands.trigger_targets(event, entity);
+        }
+
+        if !dispatched {
+            break;
+        }
+    }
+}
+
+fn data_sender(
+    mut events: EventReader<SendMessageAction>,
+    mut query: Query<(&mut Connection, &Gmcp)>,
+    controller_query: Query<&ControlledBy>,
+) {
     for event in events.read() {
-        if let Ok(mut conn) = query.get_mut(event.connection) {
-            if let TelnetEvents::Negotiation(TelnetNegotiation { command, option }) = event.data {
-                let data = match command {
-                    op_command::WILL => conn.parser._will(option),
-                    op_command::WONT => conn.parser._wont(option),
-                    op_command::DO => conn.parser._do(option),
-                    op_command::DONT => conn.parser._dont(option),
-                    _ => None,
-                };
-                if let Some(data) = data {
-                    let _ = conn.telnet_event_sender.try_send(data);
+        let connection = controller_query
+            .get(event.connection)
+            .map(|controller| controller.0)
+            .unwrap_or(event.connection);
+
+        let Ok((mut conn, gmcp)) = query.get_mut(connection) else {
+            continue;
+        };
+
+        let data = match event.data {
+            TelnetEvents::Negotiation(TelnetNegotiation { command, option }) => match command {
+                op_command::WILL => conn.parser._will(option),
+                op_command::WONT => conn.parser._wont(option),
+                op_command::DO => conn.parser._do(option),
+                op_command::DONT => conn.parser._dont(option),
+                _ => None,
+            },
+            TelnetEvents::Subnegotiation(TelnetSubnegotiation { option, ref buffer }) => {
+                if option == op_option::GMCP && !gmcp.enabled {
+                    None
+                } else {
+                    Some(TelnetEvents::DataSend(subnegotiation_bytes(option, buffer)))
                 }
-            } else {
-                let _ = conn.telnet_event_sender.try_send(event.data.clone());
             }
+            ref data => Some(data.clone()),
+        };
+
+        if let Some(data) = data {
+            let _ = conn
+                .telnet_event_sender
+                .try_send(EventHandlerMessage::Telnet(data));
+        }
+    }
+}
+
+/// Builds the bytes for a subnegotiation, escaping IAC bytes in `data`
+fn subnegotiation_bytes(option: u8, data: &[u8]) -> Bytes {
+    let mut bytes = BytesMut::with_capacity(data.len() + 5);
+    bytes.extend_from_slice(&[op_command::IAC, op_command::SB, option]);
+    bytes.extend_from_slice(&TelnetParser::escape_iac(data.to_vec()));
+    bytes.extend_from_slice(&[op_command::IAC, op_command::SE]);
+    bytes.freeze()
+}
+
+fn disconnect_handler(mut events: EventReader<DisconnectAction>, query: Query<&Connection>) {
+    for event in events.read() {
+        if let Ok(conn) = query.get(event.connection) {
+            let _ = conn
+                .telnet_event_sender
+                .try_send(EventHandlerMessage::Close);
         }
     }
 }
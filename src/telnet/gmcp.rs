@@ -0,0 +1,199 @@
+//! Generic MUD Communication Protocol (https://tintin.mudhalla.net/protocols/gmcp/)
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use libmudtelnet::telnet::{op_command, op_option};
+use serde_json::Value;
+
+use super::{
+    EventWriterTelnetEx, NegotiationReceived, NewConnection, SendMessageAction,
+    SubnegotiationReceived,
+};
+
+pub struct GmcpPlugin;
+
+impl Plugin for GmcpPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Gmcp>()
+            .add_systems(Update, offer_gmcp)
+            .add_observer(on_gmcp_negotiation)
+            .add_observer(on_gmcp_subnegotiation)
+            .add_observer(on_core_hello)
+            .add_observer(on_core_supports);
+    }
+}
+
+/// GMCP state of a connection
+#[derive(Clone, Debug, Default, Reflect, Component)]
+pub struct Gmcp {
+    /// Whether the client agreed to use GMCP
+    pub enabled: bool,
+    /// Client name and version from `Core.Hello`
+    pub client: Option<(String, String)>,
+    /// Packages the client supports, with their versions
+    pub packages: HashMap<String, u32>,
+}
+
+impl Gmcp {
+    /// Whether the client announced support for `package`, or the package containing it
+    pub fn supports(&self, package: &str) -> bool {
+        let mut package = package;
+        loop {
+            if self.packages.contains_key(package) {
+                return true;
+            }
+            match package.rfind('.') {
+                Some(idx) => package = &package[..idx],
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Fires when a GMCP message was received
+/// Event target is the connection
+#[derive(Clone, Debug, Event)]
+pub struct GmcpReceived {
+    pub package: String,
+    pub data: Value,
+}
+
+/// Fires when the client sent `Core.Hello`
+/// Event target is the connection
+#[derive(Clone, Debug, Event)]
+pub struct GmcpCoreHello {
+    pub client: String,
+    pub version: String,
+}
+
+/// Fires when the client changed its supported packages through `Core.Supports.*`
+/// Event target is the connection
+#[derive(Clone, Debug, Event)]
+pub enum GmcpCoreSupports {
+    /// Replace the supported packages
+    Set(Vec<(String, u32)>),
+    Add(Vec<(String, u32)>),
+    Remove(Vec<String>),
+}
+
+fn offer_gmcp(
+    mut new_conn: EventReader<NewConnection>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    for conn in new_conn.read() {
+        sender.negotiate(conn.entity, op_command::WILL, op_option::GMCP);
+    }
+}
+
+fn on_gmcp_negotiation(trigger: Trigger<NegotiationReceived>, mut query: Query<&mut Gmcp>) {
+    if trigger.option != op_option::GMCP {
+        return;
+    }
+
+    let Ok(mut gmcp) = query.get_mut(trigger.target()) else {
+        return;
+    };
+
+    match trigger.command {
+        op_command::DO => gmcp.enabled = true,
+        op_command::DONT => gmcp.enabled = false,
+        _ => {}
+    }
+}
+
+fn on_gmcp_subnegotiation(trigger: Trigger<SubnegotiationReceived>, mut commands: Commands) {
+    if trigger.option != op_option::GMCP {
+        return;
+    }
+
+    let message = String::from_utf8_lossy(&trigger.data).into_owned();
+    let (package, data) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+
+    let data = if data.trim().is_empty() {
+        Value::Null
+    } else {
+        match serde_json::from_str(data) {
+            Ok(data) => data,
+            Err(err) => {
+                debug!("Invalid GMCP data for {package}: {err}");
+                return;
+            }
+        }
+    };
+
+    let conn = trigger.target();
+
+    match package.to_lowercase().as_str() {
+        "core.hello" => {
+            let field = |name: &str| data[name].as_str().unwrap_or_default().to_string();
+            commands.trigger_targets(
+                GmcpCoreHello {
+                    client: field("client"),
+                    version: field("version"),
+                },
+                conn,
+            );
+        }
+        "core.supports.set" => {
+            commands.trigger_targets(GmcpCoreSupports::Set(parse_packages(&data)), conn);
+        }
+        "core.supports.add" => {
+            commands.trigger_targets(GmcpCoreSupports::Add(parse_packages(&data)), conn);
+        }
+        "core.supports.remove" => {
+            let packages = parse_packages(&data).into_iter().map(|(name, _)| name);
+            commands.trigger_targets(GmcpCoreSupports::Remove(packages.collect()), conn);
+        }
+        _ => {}
+    }
+
+    commands.trigger_targets(
+        GmcpReceived {
+            package: package.to_string(),
+            data,
+        },
+        conn,
+    );
+}
+
+/// Parses a list of packages like `["Char 1", "Room 1"]`
+fn parse_packages(data: &Value) -> Vec<(String, u32)> {
+    let Some(packages) = data.as_array() else {
+        return Vec::new();
+    };
+
+    packages
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|package| match package.split_once(' ') {
+            Some((name, version)) => (name.to_string(), version.trim().parse().unwrap_or(1)),
+            None => (package.to_string(), 1),
+        })
+        .collect()
+}
+
+fn on_core_hello(trigger: Trigger<GmcpCoreHello>, mut query: Query<&mut Gmcp>) {
+    if let Ok(mut gmcp) = query.get_mut(trigger.target()) {
+        gmcp.client = Some((trigger.client.clone(), trigger.version.clone()));
+    }
+}
+
+fn on_core_supports(trigger: Trigger<GmcpCoreSupports>, mut query: Query<&mut Gmcp>) {
+    let Ok(mut gmcp) = query.get_mut(trigger.target()) else {
+        return;
+    };
+
+    match trigger.event() {
+        GmcpCoreSupports::Set(packages) => {
+            gmcp.packages = packages.iter().cloned().collect();
+        }
+        GmcpCoreSupports::Add(packages) => {
+            gmcp.packages.extend(packages.iter().cloned());
+        }
+        GmcpCoreSupports::Remove(packages) => {
+            for package in packages {
+                gmcp.packages.remove(package);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+use libmudtelnet::bytes::{Bytes, BytesMut};
+
+/// Default maximum length of a single line of input, in bytes
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 4096;
+
+/// Assembles raw input from a client into complete lines
+///
+/// Lines can be terminated by CR LF, CR NUL, a lone CR, or a lone LF. Lines longer than the
+/// maximum length are truncated, discarding everything up to the next line terminator.
+#[derive(Debug)]
+pub struct LineBuffer {
+    buffer: BytesMut,
+    max_length: usize,
+    /// The previous byte was a CR, so a following LF or NUL is part of the same terminator
+    pending_cr: bool,
+    /// The current line went over `max_length`
+    truncated: bool,
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_LINE_LENGTH)
+    }
+}
+
+impl LineBuffer {
+    pub fn new(max_length: usize) -> Self {
+        Self {
+            buffer: BytesMut::new(),
+            max_length,
+            pending_cr: false,
+            truncated: false,
+        }
+    }
+
+    /// Adds `data` to the buffer and returns every line it completed, without line terminators
+    pub fn push(&mut self, data: &[u8]) -> Vec<Bytes> {
+        let mut lines = Vec::new();
+
+        for &byte in data {
+            if self.pending_cr {
+                self.pending_cr = false;
+                if byte == b'\n' || byte == b'\0' {
+                    continue;
+                }
+            }
+
+            match byte {
+                b'\r' => {
+                    self.pending_cr = true;
+                    lines.push(self.take_line());
+                }
+                b'\n' => lines.push(self.take_line()),
+                _ if self.buffer.len() >= self.max_length => self.truncated = true,
+                _ => self.buffer.extend_from_slice(&[byte]),
+            }
+        }
+
+        lines
+    }
+
+    fn take_line(&mut self) -> Bytes {
+        if self.truncated {
+            self.truncated = false;
+            warn!(
+                "Received line longer than {} bytes, truncating",
+                self.max_length
+            );
+        }
+        self.buffer.split().freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(buffer: &mut LineBuffer, data: &[u8]) -> Vec<String> {
+        buffer
+            .push(data)
+            .iter()
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn terminators() {
+        let mut buffer = LineBuffer::default();
+        assert_eq!(
+            push(&mut buffer, b"a\r\nb\nc\rd\r\0e"),
+            ["a", "b", "c", "d"]
+        );
+        assert_eq!(push(&mut buffer, b"\n"), ["e"]);
+    }
+
+    #[test]
+    fn empty_lines() {
+        let mut buffer = LineBuffer::default();
+        assert_eq!(push(&mut buffer, b"\r\n\n\r\r\n"), ["", "", "", ""]);
+    }
+
+    #[test]
+    fn split_across_reads() {
+        let mut buffer = LineBuffer::default();
+        assert!(push(&mut buffer, b"hel").is_empty());
+        assert_eq!(push(&mut buffer, b"lo\r"), ["hello"]);
+        // The LF belongs to the CR of the previous read
+        assert_eq!(push(&mut buffer, b"\nworld\r"), ["world"]);
+        assert_eq!(push(&mut buffer, b"\0x\n"), ["x"]);
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        let mut buffer = LineBuffer::default();
+        let mut data = vec![b'a'; DEFAULT_MAX_LINE_LENGTH + 10];
+        data.extend_from_slice(b"\nnext\n");
+
+        let lines = push(&mut buffer, &data);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), DEFAULT_MAX_LINE_LENGTH);
+        assert_eq!(lines[1], "next");
+    }
+}
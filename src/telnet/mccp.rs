@@ -0,0 +1,133 @@
+//! MUD Client Compression Protocol, versions 2 (server to client) and 3 (client to server)
+//! (https://tintin.mudhalla.net/protocols/mccp/)
+use std::io::Write;
+
+use bevy::prelude::*;
+use flate2::{
+    Compression, Decompress, DecompressError, FlushDecompress, Status, write::ZlibEncoder,
+};
+use libmudtelnet::{
+    bytes::Bytes,
+    telnet::{op_command, op_option},
+};
+
+use super::{
+    EventWriterTelnetEx, NegotiationReceived, NewConnection, SendMessageAction,
+    SubnegotiationReceived,
+};
+
+pub struct MccpPlugin;
+
+impl Plugin for MccpPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Mccp>()
+            .add_systems(Update, offer_mccp)
+            .add_observer(on_mccp_negotiation)
+            .add_observer(on_mccp3_subnegotiation);
+    }
+}
+
+/// Compression state of a connection
+#[derive(Clone, Copy, Debug, Default, Reflect, Component)]
+pub struct Mccp {
+    /// Output to the client is compressed (MCCP2)
+    pub outbound: bool,
+    /// Input from the client is compressed (MCCP3)
+    pub inbound: bool,
+}
+
+fn offer_mccp(
+    mut new_conn: EventReader<NewConnection>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    for conn in new_conn.read() {
+        sender.negotiate(conn.entity, op_command::WILL, op_option::MCCP2);
+        sender.negotiate(conn.entity, op_command::WILL, op_option::MCCP3);
+    }
+}
+
+/// Compression itself is started by the connection's event handler task, this only keeps track
+fn on_mccp_negotiation(trigger: Trigger<NegotiationReceived>, mut query: Query<&mut Mccp>) {
+    if trigger.option != op_option::MCCP2 && trigger.option != op_option::MCCP3 {
+        return;
+    }
+
+    let Ok(mut mccp) = query.get_mut(trigger.target()) else {
+        return;
+    };
+
+    match (trigger.option, trigger.command) {
+        (op_option::MCCP2, op_command::DO) => mccp.outbound = true,
+        (op_option::MCCP2, op_command::DONT) => mccp.outbound = false,
+        (op_option::MCCP3, op_command::DONT) => mccp.inbound = false,
+        _ => {}
+    }
+}
+
+fn on_mccp3_subnegotiation(trigger: Trigger<SubnegotiationReceived>, mut query: Query<&mut Mccp>) {
+    if trigger.option != op_option::MCCP3 {
+        return;
+    }
+
+    if let Ok(mut mccp) = query.get_mut(trigger.target()) {
+        mccp.inbound = true;
+    }
+}
+
+/// Compresses outgoing data into a zlib stream
+pub(super) struct Deflater(ZlibEncoder<Vec<u8>>);
+
+impl Deflater {
+    pub fn new() -> Self {
+        Self(ZlibEncoder::new(Vec::new(), Compression::default()))
+    }
+
+    /// Compresses `data`, flushing it so the client can decompress it right away
+    pub fn compress(&mut self, data: &[u8]) -> std::io::Result<Bytes> {
+        self.0.write_all(data)?;
+        self.0.flush()?;
+        Ok(Bytes::from(std::mem::take(self.0.get_mut())))
+    }
+
+    /// Ends the compressed stream, returning the remaining compressed data
+    pub fn finish(self) -> std::io::Result<Bytes> {
+        self.0.finish().map(Bytes::from)
+    }
+}
+
+/// Decompresses incoming data from a zlib stream
+pub(super) struct Inflater(Decompress);
+
+impl Inflater {
+    pub fn new() -> Self {
+        Self(Decompress::new(true))
+    }
+
+    /// Decompresses `input`
+    ///
+    /// If the compressed stream ended, the data following it is returned as well, uncompressed.
+    pub fn inflate(
+        &mut self,
+        mut input: &[u8],
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>), DecompressError> {
+        let mut output = Vec::with_capacity(input.len() * 4);
+
+        loop {
+            output.reserve(4096);
+
+            let in_before = self.0.total_in();
+            let out_before = self.0.total_out();
+            let status = self
+                .0
+                .decompress_vec(input, &mut output, FlushDecompress::None)?;
+            input = &input[(self.0.total_in() - in_before) as usize..];
+
+            if status == Status::StreamEnd {
+                return Ok((output, Some(input.to_vec())));
+            }
+            if self.0.total_in() == in_before && self.0.total_out() == out_before {
+                return Ok((output, None));
+            }
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Client identification through TERMINAL-TYPE, following the MTTS conventions
+//! (https://tintin.mudhalla.net/protocols/mtts/)
+use bevy::prelude::*;
+use libmudtelnet::telnet::{op_command, op_option};
+
+use super::{
+    EventWriterTelnetEx, NegotiationReceived, NewConnection, SendMessageAction,
+    SubnegotiationReceived,
+};
+
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+
+/// Upper bound on TTYPE requests per connection, in case a client keeps sending new names
+const MAX_TTYPE_REQUESTS: u8 = 4;
+
+pub struct MttsPlugin;
+
+impl Plugin for MttsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ClientCapabilities>()
+            .register_type::<Mtts>()
+            .add_systems(Update, request_ttype)
+            .add_observer(on_ttype_negotiation)
+            .add_observer(on_ttype_subnegotiation);
+    }
+}
+
+/// MTTS capability bitfield, as reported by the client
+///
+/// Only the flags output depends on have constants, the others are kept in the raw value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub struct Mtts(pub u32);
+
+impl Mtts {
+    pub const ANSI: u32 = 1;
+    pub const UTF8: u32 = 4;
+    pub const COLORS_256: u32 = 8;
+    pub const SCREEN_READER: u32 = 64;
+    pub const TRUECOLOR: u32 = 256;
+
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+}
+
+/// What is known about the client on the other end of a connection
+///
+/// Until the client identifies itself, it is assumed to support basic ANSI colors.
+#[derive(Clone, Debug, Reflect, Component)]
+pub struct ClientCapabilities {
+    pub client_name: Option<String>,
+    pub terminal_type: Option<String>,
+    pub mtts: Mtts,
+    /// Number of TTYPE requests sent so far
+    ttype_requests: u8,
+}
+
+impl Default for ClientCapabilities {
+    fn default() -> Self {
+        Self {
+            client_name: None,
+            terminal_type: None,
+            mtts: Mtts(Mtts::ANSI),
+            ttype_requests: 0,
+        }
+    }
+}
+
+impl ClientCapabilities {
+    pub fn ansi(&self) -> bool {
+        self.mtts.contains(Mtts::ANSI)
+    }
+
+    pub fn utf8(&self) -> bool {
+        self.mtts.contains(Mtts::UTF8)
+    }
+
+    pub fn colors_256(&self) -> bool {
+        self.mtts.contains(Mtts::COLORS_256)
+    }
+
+    pub fn screen_reader(&self) -> bool {
+        self.mtts.contains(Mtts::SCREEN_READER)
+    }
+
+    pub fn truecolor(&self) -> bool {
+        self.mtts.contains(Mtts::TRUECOLOR)
+    }
+
+    /// Colors `text` with the best color the client supports, or returns it unchanged if it
+    /// doesn't support colors at all or uses a screen reader
+    pub fn paint(&self, text: &str, color: AnsiColor) -> String {
+        let sgr = if self.screen_reader() {
+            return text.to_string();
+        } else if self.truecolor() {
+            let (r, g, b) = color.rgb();
+            format!("38;2;{r};{g};{b}")
+        } else if self.colors_256() {
+            format!("38;5;{}", color.xterm_256())
+        } else if self.ansi() {
+            color.sgr().to_string()
+        } else {
+            return text.to_string();
+        };
+
+        format!("\x1b[{sgr}m{text}\x1b[0m")
+    }
+
+    /// A horizontal line `width` characters wide, drawn with box-drawing characters if the
+    /// client supports UTF-8. Screen readers get none, as it would only be read out.
+    pub fn rule(&self, width: usize) -> Option<String> {
+        if self.screen_reader() {
+            None
+        } else if self.utf8() {
+            Some("─".repeat(width))
+        } else {
+            Some("-".repeat(width))
+        }
+    }
+
+    /// Takes the reply to a TTYPE request, and returns whether to ask for the next one
+    ///
+    /// The first reply is the client name, the second the terminal type, and the third the
+    /// MTTS bitfield. Clients that don't cycle keep sending the same name.
+    fn receive_ttype(&mut self, name: String) -> bool {
+        let done = if self.ttype_requests == 1 {
+            self.client_name = Some(name.clone());
+            self.infer_from_terminal_type(&name);
+            self.terminal_type = Some(name);
+            false
+        } else if self.terminal_type.as_ref() == Some(&name) {
+            true
+        } else if let Some(bitfield) = name.strip_prefix("MTTS ") {
+            if let Ok(bitfield) = bitfield.trim().parse() {
+                self.mtts = Mtts(bitfield);
+            }
+            true
+        } else {
+            self.infer_from_terminal_type(&name);
+            self.terminal_type = Some(name);
+            false
+        };
+
+        if done || self.ttype_requests >= MAX_TTYPE_REQUESTS {
+            return false;
+        }
+        self.ttype_requests += 1;
+        true
+    }
+
+    /// Guesses capabilities from the terminal type, for clients that don't report MTTS
+    fn infer_from_terminal_type(&mut self, terminal_type: &str) {
+        let terminal_type = terminal_type.to_uppercase();
+
+        if terminal_type.contains("256COLOR") {
+            self.mtts.0 |= Mtts::ANSI | Mtts::COLORS_256;
+        } else if terminal_type.contains("TRUECOLOR") {
+            self.mtts.0 |= Mtts::ANSI | Mtts::COLORS_256 | Mtts::TRUECOLOR;
+        } else if terminal_type == "DUMB" {
+            self.mtts.0 &= !Mtts::ANSI;
+        }
+    }
+}
+
+/// The basic ANSI colors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnsiColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+impl AnsiColor {
+    fn sgr(self) -> u8 {
+        30 + self as u8
+    }
+
+    /// The color as shown to truecolor clients
+    fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            AnsiColor::Black => (40, 40, 40),
+            AnsiColor::Red => (215, 60, 60),
+            AnsiColor::Green => (95, 190, 95),
+            AnsiColor::Yellow => (230, 200, 80),
+            AnsiColor::Blue => (80, 130, 220),
+            AnsiColor::Magenta => (190, 100, 200),
+            AnsiColor::Cyan => (80, 190, 200),
+            AnsiColor::White => (230, 230, 230),
+        }
+    }
+
+    /// The closest color in the 6x6x6 cube of the xterm 256 color palette
+    fn xterm_256(self) -> u8 {
+        let (r, g, b) = self.rgb();
+        let level = |c: u8| (c as u16 * 5 + 127) / 255;
+        (16 + 36 * level(r) + 6 * level(g) + level(b)) as u8
+    }
+}
+
+fn request_ttype(
+    mut new_conn: EventReader<NewConnection>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    for conn in new_conn.read() {
+        sender.negotiate(conn.entity, op_command::DO, op_option::TTYPE);
+    }
+}
+
+fn on_ttype_negotiation(
+    trigger: Trigger<NegotiationReceived>,
+    mut query: Query<&mut ClientCapabilities>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    if trigger.option != op_option::TTYPE || trigger.command != op_command::WILL {
+        return;
+    }
+
+    let Ok(mut capabilities) = query.get_mut(trigger.target()) else {
+        return;
+    };
+
+    if capabilities.ttype_requests == 0 {
+        capabilities.ttype_requests = 1;
+        sender.subnegotiate(trigger.target(), op_option::TTYPE, &[TTYPE_SEND]);
+    }
+}
+
+fn on_ttype_subnegotiation(
+    trigger: Trigger<SubnegotiationReceived>,
+    mut query: Query<&mut ClientCapabilities>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    if trigger.option != op_option::TTYPE {
+        return;
+    }
+
+    let Some((&TTYPE_IS, name)) = trigger.data.split_first() else {
+        return;
+    };
+    let name = String::from_utf8_lossy(name).into_owned();
+
+    let Ok(mut capabilities) = query.get_mut(trigger.target()) else {
+        return;
+    };
+
+    if capabilities.receive_ttype(name) {
+        sender.subnegotiate(trigger.target(), op_option::TTYPE, &[TTYPE_SEND]);
+    } else {
+        debug!(
+            "Client identified as {:?} ({:?}), MTTS {}",
+            capabilities.client_name, capabilities.terminal_type, capabilities.mtts.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Capabilities after the first TTYPE request was sent
+    fn requested() -> ClientCapabilities {
+        ClientCapabilities {
+            ttype_requests: 1,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn cycles_through_ttype_replies() {
+        let mut capabilities = requested();
+
+        assert!(capabilities.receive_ttype("MUDLET".to_string()));
+        assert!(capabilities.receive_ttype("XTERM-256COLOR".to_string()));
+        assert!(!capabilities.receive_ttype("MTTS 325".to_string()));
+
+        assert_eq!(capabilities.client_name.as_deref(), Some("MUDLET"));
+        assert_eq!(
+            capabilities.terminal_type.as_deref(),
+            Some("XTERM-256COLOR")
+        );
+        assert_eq!(capabilities.mtts, Mtts(325));
+    }
+
+    #[test]
+    fn stops_when_the_name_repeats() {
+        let mut capabilities = requested();
+
+        assert!(capabilities.receive_ttype("XTERM-256COLOR".to_string()));
+        assert!(!capabilities.receive_ttype("XTERM-256COLOR".to_string()));
+
+        assert_eq!(capabilities.mtts, Mtts(Mtts::ANSI | Mtts::COLORS_256));
+    }
+
+    #[test]
+    fn limits_ttype_requests() {
+        let mut capabilities = requested();
+
+        let requests = (0..10)
+            .take_while(|i| capabilities.receive_ttype(format!("TERM{i}")))
+            .count();
+        assert_eq!(requests, (MAX_TTYPE_REQUESTS - 1) as usize);
+    }
+
+    #[test]
+    fn parses_the_bitfield() {
+        let mut capabilities = requested();
+        capabilities.receive_ttype("TINTIN++".to_string());
+        capabilities.receive_ttype("XTERM".to_string());
+
+        capabilities.receive_ttype("MTTS 325".to_string());
+        assert!(capabilities.ansi());
+        assert!(capabilities.utf8());
+        assert!(capabilities.screen_reader());
+        assert!(capabilities.truecolor());
+        assert!(!capabilities.colors_256());
+    }
+
+    #[test]
+    fn ignores_an_invalid_bitfield() {
+        let mut capabilities = requested();
+        capabilities.receive_ttype("TINTIN++".to_string());
+        capabilities.receive_ttype("DUMB".to_string());
+
+        assert!(!capabilities.receive_ttype("MTTS lots".to_string()));
+        assert!(!capabilities.ansi());
+    }
+
+    #[test]
+    fn paints_for_the_client() {
+        let mut capabilities = ClientCapabilities::default();
+        assert_eq!(
+            capabilities.paint("hi", AnsiColor::Green),
+            "\x1b[32mhi\x1b[0m"
+        );
+        assert_eq!(capabilities.rule(3).as_deref(), Some("---"));
+
+        capabilities.mtts = Mtts(Mtts::ANSI | Mtts::UTF8 | Mtts::TRUECOLOR);
+        assert_eq!(
+            capabilities.paint("hi", AnsiColor::Green),
+            "\x1b[38;2;95;190;95mhi\x1b[0m"
+        );
+        assert_eq!(capabilities.rule(3).as_deref(), Some("───"));
+
+        capabilities.mtts = Mtts(Mtts::ANSI | Mtts::SCREEN_READER);
+        assert_eq!(capabilities.paint("hi", AnsiColor::Green), "hi");
+        assert_eq!(capabilities.rule(3), None);
+    }
+}
@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use libmudtelnet::telnet::{op_command, op_option};
+
+use super::{EventWriterTelnetEx, NewConnection, SendMessageAction, SubnegotiationReceived};
+
+pub struct NawsPlugin;
+
+impl Plugin for NawsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<TerminalSize>()
+            .add_systems(Update, request_naws)
+            .add_observer(on_naws_subnegotiation);
+    }
+}
+
+/// Size of the client's terminal, as reported through NAWS
+#[derive(Clone, Copy, Debug, Reflect, Component)]
+pub struct TerminalSize {
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Default for TerminalSize {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            height: 24,
+        }
+    }
+}
+
+fn request_naws(
+    mut new_conn: EventReader<NewConnection>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    for conn in new_conn.read() {
+        sender.negotiate(conn.entity, op_command::DO, op_option::NAWS);
+    }
+}
+
+fn on_naws_subnegotiation(
+    trigger: Trigger<SubnegotiationReceived>,
+    mut query: Query<&mut TerminalSize>,
+) {
+    if trigger.option != op_option::NAWS {
+        return;
+    }
+
+    let &[width_hi, width_lo, height_hi, height_lo] = &trigger.data[..] else {
+        debug!("Invalid NAWS subnegotiation: {:?}", trigger.data);
+        return;
+    };
+
+    let Ok(mut size) = query.get_mut(trigger.target()) else {
+        return;
+    };
+
+    // A size of 0 means the client doesn't know that dimension
+    let width = u16::from_be_bytes([width_hi, width_lo]);
+    let height = u16::from_be_bytes([height_hi, height_lo]);
+    if width != 0 {
+        size.width = width;
+    }
+    if height != 0 {
+        size.height = height;
+    }
+}
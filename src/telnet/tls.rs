@@ -0,0 +1,55 @@
+//! TLS-encrypted telnet
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use async_net::TcpStream;
+use bevy::{
+    asset::AsyncWriteExt,
+    tasks::futures_lite::io::{ReadHalf, WriteHalf},
+};
+use futures_rustls::{TlsAcceptor, rustls::ServerConfig, server::TlsStream};
+
+use super::TransportWriter;
+
+/// Builds an acceptor from a PEM encoded certificate chain and private key
+pub(super) fn acceptor(certificate: &Path, key: &Path) -> Result<TlsAcceptor, String> {
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(certificate).map_err(|err| format!("{}: {err}", certificate.display()))?,
+    ))
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|err| format!("{}: {err}", certificate.display()))?;
+
+    let private_key = rustls_pemfile::private_key(&mut BufReader::new(
+        File::open(key).map_err(|err| format!("{}: {err}", key.display()))?,
+    ))
+    .map_err(|err| format!("{}: {err}", key.display()))?
+    .ok_or_else(|| format!("{}: No private key found", key.display()))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, private_key)
+        .map_err(|err| err.to_string())?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Splits a TLS stream into halves for `connection_reader` and the event handler
+pub(super) fn split(
+    stream: TlsStream<TcpStream>,
+) -> (
+    ReadHalf<TlsStream<TcpStream>>,
+    WriteHalf<TlsStream<TcpStream>>,
+) {
+    bevy::tasks::futures_lite::io::split(stream)
+}
+
+impl TransportWriter for WriteHalf<TlsStream<TcpStream>> {
+    async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.write_all(data).await
+    }
+
+    async fn finish(&mut self) {
+        let _ = self.flush().await;
+        // Sends close_notify and shuts down the socket
+        let _ = self.close().await;
+    }
+}
@@ -0,0 +1,213 @@
+//! WebSocket transport, so web clients can connect without a proxy
+//!
+//! Clients asking for the `telnet` subprotocol exchange raw telnet data in binary frames. All
+//! other clients get plain text: every text frame is one line of input or output, and telnet
+//! commands are stripped from the output. Output is held back until a line is complete, or until
+//! a GA marks the end of a prompt.
+use async_net::TcpStream;
+use async_tungstenite::{
+    WebSocketStream,
+    tungstenite::{
+        Message,
+        handshake::server::{ErrorResponse, Request, Response},
+        http::HeaderValue,
+    },
+};
+use bevy::prelude::*;
+use futures_util::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use libmudtelnet::{
+    bytes::{Bytes, BytesMut},
+    telnet::op_command,
+};
+
+use super::TransportWriter;
+
+const TELNET_SUBPROTOCOL: &str = "telnet";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum WebSocketMode {
+    /// Lines of text in text frames
+    Text,
+    /// Telnet data in binary frames
+    Telnet,
+}
+
+/// Performs the WebSocket handshake on a freshly accepted TCP stream
+pub(super) async fn accept(
+    stream: TcpStream,
+) -> Result<(WebSocketStream<TcpStream>, WebSocketMode), async_tungstenite::tungstenite::Error> {
+    let mut mode = WebSocketMode::Text;
+
+    let websocket = async_tungstenite::accept_hdr_async(
+        stream,
+        |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            let telnet = request
+                .headers()
+                .get_all("Sec-WebSocket-Protocol")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|protocol| protocol.trim() == TELNET_SUBPROTOCOL);
+
+            if telnet {
+                mode = WebSocketMode::Telnet;
+                response.headers_mut().insert(
+                    "Sec-WebSocket-Protocol",
+                    HeaderValue::from_static(TELNET_SUBPROTOCOL),
+                );
+            }
+
+            Ok(response)
+        },
+    )
+    .await?;
+
+    Ok((websocket, mode))
+}
+
+/// Reads messages from `stream` and sends their data to `sender`
+pub(super) async fn websocket_reader(
+    mut stream: SplitStream<WebSocketStream<TcpStream>>,
+    mode: WebSocketMode,
+    sender: async_channel::Sender<Bytes>,
+) {
+    while let Some(Ok(message)) = stream.next().await {
+        let data = match message {
+            Message::Text(text) if mode == WebSocketMode::Text => {
+                let mut data = BytesMut::from(text.as_bytes());
+                if !text.ends_with('\n') {
+                    data.extend_from_slice(b"\r\n");
+                }
+                data.freeze()
+            }
+            Message::Text(text) => Bytes::copy_from_slice(text.as_bytes()),
+            Message::Binary(data) => Bytes::copy_from_slice(&data),
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        if sender.send(data).await.is_err() {
+            break;
+        }
+    }
+
+    // Connection closed
+    sender.close();
+}
+
+pub(super) struct WebSocketWriter {
+    sink: SplitSink<WebSocketStream<TcpStream>, Message>,
+    mode: WebSocketMode,
+    /// Text mode output that doesn't make up a whole line yet
+    pending: Vec<u8>,
+}
+
+impl WebSocketWriter {
+    /// Sends the first `end` bytes of pending output, one text frame per line
+    async fn send_lines(&mut self, end: usize) -> std::io::Result<()> {
+        let text: Vec<u8> = self.pending.drain(..end).collect();
+
+        for line in text.split_inclusive(|&byte| byte == b'\n') {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            self.sink
+                .send(Message::text(String::from_utf8_lossy(line).into_owned()))
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TransportWriter for WebSocketWriter {
+    async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if self.mode == WebSocketMode::Telnet {
+            return self
+                .sink
+                .send(Message::binary(data.to_vec()))
+                .await
+                .map_err(std::io::Error::other);
+        }
+
+        let (text, prompt_end) = strip_telnet(data);
+        let prompt_end = prompt_end.map(|end| self.pending.len() + end);
+        self.pending.extend_from_slice(&text);
+
+        let line_end = self
+            .pending
+            .iter()
+            .rposition(|&byte| byte == b'\n')
+            .map(|i| i + 1);
+
+        match line_end.max(prompt_end) {
+            Some(end) if end > 0 => self.send_lines(end).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.send_lines(self.pending.len()).await;
+        }
+        if let Err(err) = self.sink.close().await {
+            debug!("Failed to close websocket: {}", err);
+        }
+    }
+}
+
+/// Removes telnet commands from outgoing data, leaving only the text
+///
+/// Also returns the length of the text up to the last GA, which ends a prompt.
+fn strip_telnet(data: &[u8]) -> (Vec<u8>, Option<usize>) {
+    let mut text = Vec::with_capacity(data.len());
+    let mut prompt_end = None;
+    let mut iter = data.iter().copied();
+
+    while let Some(byte) = iter.next() {
+        if byte != op_command::IAC {
+            text.push(byte);
+            continue;
+        }
+
+        match iter.next() {
+            Some(op_command::IAC) => text.push(op_command::IAC),
+            Some(op_command::GA) => prompt_end = Some(text.len()),
+            Some(op_command::WILL | op_command::WONT | op_command::DO | op_command::DONT) => {
+                iter.next();
+            }
+            Some(op_command::SB) => {
+                let mut previous = 0;
+                for byte in iter.by_ref() {
+                    if previous == op_command::IAC && byte == op_command::SE {
+                        break;
+                    }
+                    // An escaped IAC inside the subnegotiation must not pair up with what follows
+                    previous = if previous == op_command::IAC { 0 } else { byte };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (text, prompt_end)
+}
+
+/// Splits a websocket into a stream for [`websocket_reader`] and a writer for the event handler
+pub(super) fn split(
+    websocket: WebSocketStream<TcpStream>,
+    mode: WebSocketMode,
+) -> (SplitStream<WebSocketStream<TcpStream>>, WebSocketWriter) {
+    let (sink, stream) = websocket.split();
+    (
+        stream,
+        WebSocketWriter {
+            sink,
+            mode,
+            pending: Vec::new(),
+        },
+    )
+}
@@ -1,41 +1,10 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use bevy::{
-    ecs::system::{IntoObserverSystem, SystemParam, SystemParamValidationError},
+    ecs::system::{SystemParam, SystemParamValidationError},
     prelude::*,
 };
 
-pub trait EntityCommandsEx {
-    fn observe_once<E, B, M>(&mut self, system: impl IntoObserverSystem<E, B, M>) -> &mut Self
-    where
-        E: Event,
-        B: Bundle;
-}
-
-impl EntityCommandsEx for EntityCommands<'_> {
-    fn observe_once<E, B, M>(&mut self, system: impl IntoObserverSystem<E, B, M>) -> &mut Self
-    where
-        E: Event,
-        B: Bundle,
-    {
-        let ent_id = self.id();
-        let mut commands = self.commands();
-
-        let mut obs_ent = commands.spawn_empty();
-        let obs_id = obs_ent.id();
-
-        let sys = IntoObserverSystem::into_system(system);
-        let mut observer = Observer::new(sys.pipe(
-            move |_: In<Result>, mut commands: Commands| -> Result {
-                commands.entity(obs_id).despawn();
-                Ok(())
-            },
-        ));
-        observer = observer.with_entity(ent_id);
-        obs_ent.insert(observer);
-
-        self
-    }
-}
-
 pub trait FutureEx<O> {
     async fn print_result(self) -> Result<O>;
 }
@@ -115,3 +84,108 @@ unsafe impl<'a, 'b, T: SystemParam> SystemParam for When<'a, 'b, T> {
             .map_err(|err| SystemParamValidationError::skipped::<Self>(err.message))
     }
 }
+
+/// Seconds since the Unix epoch, as stored in the database
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Wraps `text` into lines of at most `width` characters, joined with CR LF
+///
+/// Lines are broken at spaces where possible. ANSI escape sequences don't count towards the
+/// width. A trailing line break is kept.
+pub fn word_wrap(text: &str, width: usize) -> String {
+    let width = width.max(1);
+    let mut wrapped = String::with_capacity(text.len());
+
+    for (i, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if i > 0 {
+            wrapped.push_str("\r\n");
+        }
+
+        let mut column = 0;
+        for (j, word) in line.split(' ').enumerate() {
+            if j > 0 {
+                if column + 1 + visible_width(word) <= width {
+                    wrapped.push(' ');
+                    column += 1;
+                } else {
+                    wrapped.push_str("\r\n");
+                    column = 0;
+                }
+            }
+
+            // Words that don't fit on a line of their own get split up
+            let mut in_escape = false;
+            for c in word.chars() {
+                if in_escape || c == '\x1b' {
+                    in_escape = c == '\x1b' || !c.is_ascii_alphabetic();
+                } else {
+                    if column == width {
+                        wrapped.push_str("\r\n");
+                        column = 0;
+                    }
+                    column += 1;
+                }
+                wrapped.push(c);
+            }
+        }
+    }
+
+    wrapped
+}
+
+/// Number of characters in `text`, not counting ANSI escape sequences
+fn visible_width(text: &str) -> usize {
+    let mut in_escape = false;
+    text.chars()
+        .filter(|&c| {
+            if in_escape || c == '\x1b' {
+                in_escape = c == '\x1b' || !c.is_ascii_alphabetic();
+                false
+            } else {
+                true
+            }
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_at_spaces() {
+        assert_eq!(
+            word_wrap("the quick brown fox", 10),
+            "the quick\r\nbrown fox"
+        );
+        assert_eq!(word_wrap("short", 10), "short");
+    }
+
+    #[test]
+    fn splits_long_words() {
+        assert_eq!(word_wrap("abcdefghij", 4), "abcd\r\nefgh\r\nij");
+    }
+
+    #[test]
+    fn keeps_line_breaks() {
+        assert_eq!(word_wrap("one\ntwo\r\nthree", 10), "one\r\ntwo\r\nthree");
+        assert_eq!(word_wrap("one\r\n", 10), "one\r\n");
+        assert_eq!(word_wrap("one\n\ntwo", 10), "one\r\n\r\ntwo");
+        assert_eq!(word_wrap("", 10), "");
+    }
+
+    #[test]
+    fn ignores_escape_sequences() {
+        assert_eq!(
+            word_wrap("\x1b[1;31mred\x1b[0m text", 8),
+            "\x1b[1;31mred\x1b[0m text"
+        );
+        assert_eq!(visible_width("\x1b[38;5;196mred\x1b[0m"), 3);
+    }
+}
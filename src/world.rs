@@ -1,8 +1,16 @@
+use std::collections::{HashMap, HashSet};
+
+use area::FromAreaFile;
 use bevy::prelude::*;
 use exit::{Exit, InExit, OutExit};
+use room::{Area, Room};
 
-use crate::misc::{Description, Id};
+use crate::{
+    database::{self, DatabaseCommandsEx},
+    misc::{Description, Id},
+};
 
+pub mod area;
 pub mod exit;
 pub mod room;
 
@@ -10,29 +18,98 @@ pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((room::RoomPlugin, exit::ExitPlugin));
+        app.add_plugins((room::RoomPlugin, exit::ExitPlugin, area::AreaPlugin));
 
-        app.add_systems(Startup, insert_test_rooms);
+        app.add_systems(PreStartup, load_world.after(database::DatabaseSystemSet));
     }
 }
 
-fn insert_test_rooms(mut commands: Commands) {
-    let room1 = commands
-        .spawn((
-            room::Room,
-            Name::new("Test"),
-            Description::new("A simple room. Nothing to see here."),
-            Id(1),
-        ))
-        .id();
-    let room2 = commands
-        .spawn((
-            room::Room,
-            Name::new("Test2"),
-            Description::new("Another room."),
-            Id(2),
-        ))
-        .id();
-    commands.spawn((Exit::new("north"), OutExit(room1), InExit(room2)));
-    commands.spawn((Exit::new("south"), OutExit(room2), InExit(room1)));
+/// A row of the `rooms` table, with the name of its area
+#[derive(sqlx::FromRow)]
+struct RoomRow {
+    id: i64,
+    area: Option<String>,
+    name: String,
+    description: String,
+}
+
+/// A row of the `exits` table
+#[derive(sqlx::FromRow)]
+struct ExitRow {
+    from_room: i64,
+    to_room: i64,
+    direction: String,
+}
+
+/// Loads the rooms and exits stored in the database
+///
+/// Rooms from the database and from area files share one id space, and exits refer to rooms by
+/// it. An id may only be used once: whichever room with that id is loaded first is kept, and the
+/// other one is skipped with a warning.
+fn load_world(mut commands: Commands) {
+    commands.run_sql(
+        async |pool| {
+            let rooms: Vec<RoomRow> = sqlx::query_as(
+                "SELECT rooms.id, areas.name AS area, rooms.name, rooms.description
+                FROM rooms LEFT JOIN areas ON areas.id = rooms.area",
+            )
+            .fetch_all(&pool)
+            .await?;
+
+            let exits: Vec<ExitRow> =
+                sqlx::query_as("SELECT from_room, to_room, direction FROM exits ORDER BY id")
+                    .fetch_all(&pool)
+                    .await?;
+
+            Ok((rooms, exits))
+        },
+        |In((rooms, exits)): In<(Vec<RoomRow>, Vec<ExitRow>)>,
+         mut commands: Commands,
+         area_room_query: Query<&Id, (With<Room>, With<FromAreaFile>)>| {
+            let area_rooms: HashSet<u64> = area_room_query.iter().map(|id| id.0).collect();
+            let mut room_entities = HashMap::with_capacity(rooms.len());
+
+            for room in rooms {
+                if area_rooms.contains(&(room.id as u64)) {
+                    warn!(
+                        "Room {} is already defined by an area file, skipping the one in the database",
+                        room.id
+                    );
+                    continue;
+                }
+
+                let mut entity = commands.spawn((
+                    Room,
+                    Name::new(room.name),
+                    Description::new(room.description),
+                    Id(room.id as u64),
+                ));
+                if let Some(area) = room.area {
+                    entity.insert(Area(area));
+                }
+                room_entities.insert(room.id, entity.id());
+            }
+
+            for exit in &exits {
+                let (Some(&from), Some(&to)) = (
+                    room_entities.get(&exit.from_room),
+                    room_entities.get(&exit.to_room),
+                ) else {
+                    warn!(
+                        "Exit {} from room {} leads to a room that does not exist",
+                        exit.direction, exit.from_room
+                    );
+                    continue;
+                };
+
+                commands.spawn((Exit::new(exit.direction.clone()), OutExit(from), InExit(to)));
+            }
+
+            info!(
+                "Loaded {} rooms and {} exits",
+                room_entities.len(),
+                exits.len()
+            );
+        },
+    );
 }
@@ -0,0 +1,345 @@
+//! Areas described in RON files under `assets/areas/`
+//!
+//! Every `*.area.ron` file is loaded as an [`AreaAsset`] and turned into rooms, exits and spawns.
+//! When a file changes on disk, the rooms it describes are updated in place, so players standing
+//! in them stay where they are.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedFolder, io::Reader},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::{
+    misc::{Description, Id},
+    session::Character,
+};
+
+use super::{
+    exit::{Exit, InExit, OutExit, OutExits},
+    room::{Area, InRoom, Room, RoomContents},
+};
+
+pub struct AreaPlugin;
+
+impl Plugin for AreaPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<AreaAsset>()
+            .init_asset_loader::<AreaLoader>()
+            .register_type::<FromAreaFile>()
+            .register_type::<AreaSpawn>()
+            .register_type::<ExitTarget>()
+            .add_systems(Startup, load_areas)
+            .add_systems(Update, (sync_areas, resolve_exit_targets).chain());
+    }
+}
+
+/// Contents of an area file
+#[derive(Asset, TypePath, Clone, Debug, Deserialize)]
+pub struct AreaAsset {
+    pub name: String,
+    #[serde(default)]
+    pub rooms: Vec<RoomDef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RoomDef {
+    /// Must not be used by another area file or by a room in the database's `rooms` table
+    pub id: u64,
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub exits: Vec<ExitDef>,
+    /// Things placed in the room when the area is loaded
+    #[serde(default)]
+    pub spawns: Vec<SpawnDef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExitDef {
+    pub direction: String,
+    /// Id of the room the exit leads to, which may be in another area
+    pub to: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpawnDef {
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Default)]
+struct AreaLoader;
+
+#[derive(Debug)]
+enum AreaLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl Display for AreaLoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AreaLoaderError::Io(err) => write!(f, "Could not read area file: {err}"),
+            AreaLoaderError::Ron(err) => write!(f, "Invalid area file: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AreaLoaderError {}
+
+impl AssetLoader for AreaLoader {
+    type Asset = AreaAsset;
+    type Settings = ();
+    type Error = AreaLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<AreaAsset, AreaLoaderError> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(AreaLoaderError::Io)?;
+        ron::de::from_bytes(&bytes).map_err(AreaLoaderError::Ron)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["area.ron"]
+    }
+}
+
+/// Keeps the area files loaded, so changes to them are picked up
+#[derive(Resource)]
+struct AreaFolder {
+    _handle: Handle<LoadedFolder>,
+}
+
+/// Placed on rooms created from an area file
+#[derive(Clone, Copy, Debug, Reflect, Component)]
+pub struct FromAreaFile(pub AssetId<AreaAsset>);
+
+/// Placed on things spawned into a room by an area file
+#[derive(Clone, Copy, Debug, Reflect, Component)]
+pub struct AreaSpawn;
+
+/// Id of the room an exit leads to, until that room has been loaded
+#[derive(Clone, Copy, Debug, Reflect, Component)]
+pub struct ExitTarget(pub u64);
+
+fn load_areas(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(AreaFolder {
+        _handle: asset_server.load_folder("areas"),
+    });
+}
+
+fn sync_areas(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<AreaAsset>>,
+    areas: Res<Assets<AreaAsset>>,
+    room_query: Query<(Entity, &Id, Option<&FromAreaFile>), With<Room>>,
+    exits_query: Query<&OutExits>,
+    contents_query: Query<&RoomContents>,
+    spawn_filter: Query<(), With<AreaSpawn>>,
+    character_filter: Query<(), With<Character>>,
+) {
+    // A file can be both added and modified in the same frame, it only needs to be read once
+    let mut changed = Vec::new();
+    let mut removed = Vec::new();
+    for event in events.read() {
+        match *event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                removed.retain(|&removed| removed != id);
+                if !changed.contains(&id) {
+                    changed.push(id);
+                }
+            }
+            AssetEvent::Removed { id } => {
+                changed.retain(|&changed| changed != id);
+                if !removed.contains(&id) {
+                    removed.push(id);
+                }
+            }
+            _ => {}
+        }
+    }
+    if changed.is_empty() && removed.is_empty() {
+        return;
+    }
+
+    // Rooms by id and the file they came from. Rooms spawned below are added, since the query
+    // doesn't see them until the commands are applied.
+    let mut rooms: HashMap<u64, (Entity, Option<AssetId<AreaAsset>>)> = room_query
+        .iter()
+        .map(|(entity, room_id, source)| (room_id.0, (entity, source.map(|source| source.0))))
+        .collect();
+
+    for id in changed {
+        let Some(area) = areas.get(id) else {
+            continue;
+        };
+        let defined: HashSet<u64> = area.rooms.iter().map(|room| room.id).collect();
+
+        for def in &area.rooms {
+            let room = match rooms.get(&def.id) {
+                Some(&(room, Some(source))) if source == id => {
+                    // Replace everything the file defines, but leave characters where they are
+                    if let Ok(exits) = exits_query.get(room) {
+                        for exit in exits.iter() {
+                            commands.entity(exit).despawn();
+                        }
+                    }
+                    if let Ok(contents) = contents_query.get(room) {
+                        for entity in contents.iter() {
+                            if spawn_filter.contains(entity) {
+                                commands.entity(entity).despawn();
+                            }
+                        }
+                    }
+                    room
+                }
+                Some((_, source)) => {
+                    let owner = match source {
+                        Some(_) => "another area file",
+                        None => "the database",
+                    };
+                    warn!(
+                        "Room {} in area {} is already defined by {owner}, skipping it",
+                        def.id, area.name
+                    );
+                    continue;
+                }
+                None => {
+                    let room = commands.spawn((Room, Id(def.id))).id();
+                    rooms.insert(def.id, (room, Some(id)));
+                    room
+                }
+            };
+
+            commands.entity(room).insert((
+                Name::new(def.name.clone()),
+                Description::new(def.description.clone()),
+                Area(area.name.clone()),
+                FromAreaFile(id),
+            ));
+
+            for exit in &def.exits {
+                commands.spawn((
+                    Exit::new(exit.direction.clone()),
+                    OutExit(room),
+                    ExitTarget(exit.to),
+                ));
+            }
+
+            for spawn in &def.spawns {
+                commands.spawn((
+                    Name::new(spawn.name.clone()),
+                    Description::new(spawn.description.clone()),
+                    AreaSpawn,
+                    InRoom(room),
+                ));
+            }
+        }
+
+        // Rooms that were removed from the file
+        despawn_dropped_rooms(
+            &mut commands,
+            &mut rooms,
+            id,
+            &defined,
+            &contents_query,
+            &spawn_filter,
+            &character_filter,
+        );
+
+        info!("Loaded area {} with {} rooms", area.name, area.rooms.len());
+    }
+
+    for id in removed {
+        despawn_dropped_rooms(
+            &mut commands,
+            &mut rooms,
+            id,
+            &HashSet::new(),
+            &contents_query,
+            &spawn_filter,
+            &character_filter,
+        );
+        info!("Unloaded area file {id:?}");
+    }
+}
+
+/// Despawns the rooms loaded from `source` that aren't in `defined` any more, along with the
+/// things the file spawned in them. Rooms with characters in them are kept.
+fn despawn_dropped_rooms(
+    commands: &mut Commands,
+    rooms: &mut HashMap<u64, (Entity, Option<AssetId<AreaAsset>>)>,
+    source: AssetId<AreaAsset>,
+    defined: &HashSet<u64>,
+    contents_query: &Query<&RoomContents>,
+    spawn_filter: &Query<(), With<AreaSpawn>>,
+    character_filter: &Query<(), With<Character>>,
+) {
+    rooms.retain(|room_id, &mut (room, room_source)| {
+        if room_source != Some(source) || defined.contains(room_id) {
+            return true;
+        }
+
+        let contents = contents_query.get(room).ok();
+        if contents.is_some_and(|contents| {
+            contents
+                .iter()
+                .any(|entity| character_filter.contains(entity))
+        }) {
+            warn!("Room {room_id} was removed from its area file, but is occupied");
+            return true;
+        }
+
+        for entity in contents.into_iter().flat_map(|contents| contents.iter()) {
+            if spawn_filter.contains(entity) {
+                commands.entity(entity).despawn();
+            }
+        }
+        commands.entity(room).despawn();
+        false
+    });
+}
+
+/// Connects exits to their target room, once it exists. Exits that were just loaded and lead
+/// nowhere are warned about once. Only runs when exits or rooms were added, since dangling exits
+/// would otherwise be looked up every frame.
+fn resolve_exit_targets(
+    mut commands: Commands,
+    exit_query: Query<(Entity, Ref<ExitTarget>, &Exit, &OutExit)>,
+    room_query: Query<(Entity, &Id), With<Room>>,
+    added_exits: Query<(), Added<ExitTarget>>,
+    added_rooms: Query<(), Added<Room>>,
+) {
+    if exit_query.is_empty() || (added_exits.is_empty() && added_rooms.is_empty()) {
+        return;
+    }
+
+    let rooms: HashMap<u64, Entity> = room_query.iter().map(|(room, id)| (id.0, room)).collect();
+
+    for (exit, target, def, from) in &exit_query {
+        if let Some(&room) = rooms.get(&target.0) {
+            commands
+                .entity(exit)
+                .remove::<ExitTarget>()
+                .insert(InExit(room));
+        } else if target.is_added() {
+            let from = room_query.get(from.0).map_or(0, |(_, id)| id.0);
+            warn!(
+                "Exit {} from room {from} leads to room {}, which does not exist",
+                def.direction, target.0
+            );
+        }
+    }
+}
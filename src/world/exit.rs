@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 
 use crate::{
-    player_commands::ExplorationCommandEvent,
+    player_commands::{AppCommandRegistryEx, CommandDef, ExplorationCommandEvent},
     telnet::{EventWriterTelnetEx, SendMessageAction},
 };
 
@@ -16,7 +16,12 @@ impl Plugin for ExitPlugin {
             .register_type::<InExit>()
             .register_type::<InExits>()
             .register_type::<OutExits>()
-            .add_observer(exits_command);
+            .add_command(
+                CommandDef::new("exits", "List the exits of the room")
+                    .min_prefix(2)
+                    .requires::<InRoom>(),
+                exits_command,
+            );
     }
 }
 
@@ -63,20 +68,18 @@ fn exits_command(
     out_exit_query: Query<&OutExits>,
     exit_query: Query<&Exit>,
 ) -> Result {
-    if trigger.command == "exits" {
-        let conn = trigger.target();
+    let conn = trigger.character;
 
-        let room = room_query.get(conn)?.0;
+    let room = room_query.get(conn)?.0;
 
-        let Ok(exits) = out_exit_query.get(room) else {
-            sender.println(conn, "No visible exits.");
-            return Ok(());
-        };
+    let Ok(exits) = out_exit_query.get(room) else {
+        sender.println(conn, "No visible exits.");
+        return Ok(());
+    };
 
-        for exit_ent in &exits.0 {
-            if let Ok(exit) = exit_query.get(*exit_ent) {
-                sender.println(conn, &exit.direction);
-            }
+    for exit_ent in &exits.0 {
+        if let Ok(exit) = exit_query.get(*exit_ent) {
+            sender.println(conn, &exit.direction);
         }
     }
 
@@ -1,24 +1,39 @@
 use bevy::prelude::*;
+use serde_json::json;
 
 use crate::{
     auth::CharacterLoginEvent,
     misc::{Description, Id},
-    player_commands::ExplorationCommandEvent,
-    telnet::{Connection, EventWriterTelnetEx, SendMessageAction},
+    player_commands::{AppCommandRegistryEx, CommandDef, ExplorationCommandEvent},
+    session::{ControlledBy, LinkDead},
+    telnet::{
+        AnsiColor, ClientCapabilities, EventWriterTelnetEx, Gmcp, SendMessageAction, TerminalSize,
+    },
+    util::word_wrap,
 };
 
+use super::exit::{Exit, InExit, OutExits};
+
 pub struct RoomPlugin;
 
 impl Plugin for RoomPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<Room>()
+            .register_type::<Area>()
             .add_observer(on_login)
             .add_observer(on_move_room_action)
             .add_observer(on_show_room_description_action)
-            .add_observer(on_look_command)
+            .add_command(
+                CommandDef::new("look", "Look around, or at something in the room")
+                    .min_prefix(1)
+                    .priority(10)
+                    .requires::<InRoom>(),
+                on_look_command,
+            )
             .add_observer(on_room_broadcast_action)
             .add_observer(room_enter_broadcast)
-            .add_observer(room_enter_description);
+            .add_observer(room_enter_description)
+            .add_observer(room_enter_gmcp);
     }
 }
 
@@ -27,15 +42,8 @@ fn on_login(
     mut commands: Commands,
     query: Query<(Entity, &Id), With<Room>>,
 ) {
-    let mut room = None;
-    for (room_ent, room_id) in query.iter() {
-        if room_id.0 == trigger.room {
-            room = Some(room_ent);
-            break;
-        }
-    }
-    let Some(room) = room else {
-        // TODO
+    let Some((room, _)) = query.iter().find(|(_, id)| id.0 == trigger.room) else {
+        warn!("Room {} does not exist", trigger.room);
         return;
     };
 
@@ -73,10 +81,56 @@ fn room_enter_description(trigger: Trigger<EnterRoomEvent>, mut commands: Comman
     );
 }
 
+/// Sends `Room.Info` to GMCP clients entering a room, if they support the `Room` package
+fn room_enter_gmcp(
+    trigger: Trigger<EnterRoomEvent>,
+    room_query: Query<(&Id, &Name, Option<&Area>, Option<&OutExits>), With<Room>>,
+    exit_query: Query<(&Exit, &InExit)>,
+    id_query: Query<&Id>,
+    controller_query: Query<&ControlledBy>,
+    gmcp_query: Query<&Gmcp>,
+    mut sender: EventWriter<SendMessageAction>,
+) {
+    let Ok(controller) = controller_query.get(trigger.entity) else {
+        return;
+    };
+    if !gmcp_query
+        .get(controller.0)
+        .is_ok_and(|gmcp| gmcp.supports("Room.Info"))
+    {
+        return;
+    }
+
+    let Ok((id, name, area, exits)) = room_query.get(trigger.target()) else {
+        return;
+    };
+
+    let exits: serde_json::Map<String, serde_json::Value> = exits
+        .into_iter()
+        .flat_map(|exits| exits.iter())
+        .filter_map(|exit| exit_query.get(exit).ok())
+        .filter_map(|(exit, target)| {
+            let target = id_query.get(target.0).ok()?;
+            Some((exit.direction.clone(), json!(target.0)))
+        })
+        .collect();
+
+    sender.send_gmcp(
+        trigger.entity,
+        "Room.Info",
+        &json!({
+            "num": id.0,
+            "name": name.as_str(),
+            "area": area.map(|area| area.0.as_str()).unwrap_or_default(),
+            "exits": exits,
+        }),
+    );
+}
+
 fn room_enter_broadcast(
     trigger: Trigger<EnterRoomEvent>,
     query: Query<&RoomContents>,
-    player_filter: Query<(), With<Connection>>,
+    player_filter: Query<(), With<ControlledBy>>,
     name_query: Query<&Name>,
     mut sender: EventWriter<SendMessageAction>,
 ) {
@@ -98,21 +152,33 @@ fn room_enter_broadcast(
 fn on_show_room_description_action(
     trigger: Trigger<ShowRoomDescriptionAction>,
     room_query: Query<(&Name, &Description, Option<&RoomContents>), With<Room>>,
-    items_query: Query<&Name>,
+    items_query: Query<(&Name, Has<LinkDead>)>,
+    controller_query: Query<&ControlledBy>,
+    client_query: Query<(&TerminalSize, &ClientCapabilities)>,
     mut sender: EventWriter<SendMessageAction>,
 ) {
     let conn = trigger.target();
 
+    let connection = controller_query
+        .get(conn)
+        .map(|controller| controller.0)
+        .unwrap_or(conn);
+    let (size, capabilities) = client_query
+        .get(connection)
+        .map(|(size, capabilities)| (*size, capabilities.clone()))
+        .unwrap_or_default();
+
     let Ok((name, description, contents)) = room_query.get(trigger.room) else {
         // Room not loaded
         return;
     };
 
     sender.println(conn, "");
-    sender.print(conn, "\x1b[32m");
-    sender.print(conn, name.as_str());
-    sender.println(conn, "\x1b[0m");
-    sender.println(conn, &description.0);
+    sender.println(conn, &capabilities.paint(name.as_str(), AnsiColor::Green));
+    if let Some(rule) = capabilities.rule(name.chars().count()) {
+        sender.println(conn, &rule);
+    }
+    sender.println(conn, &word_wrap(&description.0, size.width.into()));
 
     if let Some(contents) = contents
         && !contents.0.is_empty()
@@ -121,10 +187,14 @@ fn on_show_room_description_action(
         sender.println(conn, "You see here:");
 
         for item in contents.iter() {
-            let Ok(name) = items_query.get(item) else {
+            let Ok((name, link_dead)) = items_query.get(item) else {
                 continue;
             };
-            sender.println(conn, name.as_str());
+            if link_dead {
+                sender.println(conn, &format!("{name} (link-dead)"));
+            } else {
+                sender.println(conn, name.as_str());
+            }
         }
     }
 }
@@ -133,14 +203,30 @@ fn on_look_command(
     trigger: Trigger<ExplorationCommandEvent>,
     mut commands: Commands,
     room_query: Query<&InRoom>,
+    contents_query: Query<&RoomContents>,
+    name_query: Query<&Name>,
+    description_query: Query<&Description>,
+    mut sender: EventWriter<SendMessageAction>,
 ) -> Result {
-    if trigger.command == "look" || trigger.command == "l" {
-        commands.trigger_targets(
-            ShowRoomDescriptionAction {
-                room: room_query.get(trigger.target())?.0,
-            },
-            trigger.target(),
-        );
+    let character = trigger.character;
+    let room = room_query.get(character)?.0;
+
+    let Some(target) = trigger.parse_target() else {
+        commands.trigger_targets(ShowRoomDescriptionAction { room }, character);
+        return Ok(());
+    };
+
+    let found = target.resolve(room, &contents_query, &name_query);
+    if found.is_empty() {
+        sender.println(character, "You don't see that here.");
+    }
+
+    for entity in found {
+        let name = name_query.get(entity)?;
+        match description_query.get(entity) {
+            Ok(description) => sender.println(character, &format!("{name}: {}", description.0)),
+            Err(_) => sender.println(character, &format!("You see nothing special about {name}.")),
+        }
     }
     Ok(())
 }
@@ -149,7 +235,7 @@ fn on_room_broadcast_action(
     trigger: Trigger<RoomBroadcastAction>,
     mut sender: EventWriter<SendMessageAction>,
     contents_query: Query<&RoomContents>,
-    player_filter: Query<(), With<Connection>>,
+    player_filter: Query<(), With<ControlledBy>>,
 ) -> Result {
     let room = trigger.target();
     let message = &trigger.message;
@@ -203,6 +289,10 @@ pub struct RoomBroadcastAction {
 #[derive(Copy, Clone, Debug, Reflect, Component)]
 pub struct Room;
 
+/// Name of the area a room belongs to
+#[derive(Clone, Debug, Reflect, Component)]
+pub struct Area(pub String);
+
 #[derive(Component, Debug, Reflect)]
 #[relationship(relationship_target = RoomContents)]
 pub struct InRoom(pub Entity);